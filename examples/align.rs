@@ -0,0 +1,97 @@
+use cpa::align::{ElasticAlign, StaticAlign};
+use cpa::cpa_normal::*;
+use cpa::leakage::{hw, sbox};
+use cpa::tools::read_array_2_from_npy_file;
+use ndarray::*;
+use std::time::{self};
+
+// traces format
+type FormatTraces = f64;
+type FormatMetadata = u8;
+
+// leakage model
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[1] ^ guess) as u8) as usize)
+}
+
+fn cpa_aligned() {
+    let size: usize = 5000; // Number of samples
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data/cw");
+    let dir_l = format!("{folder}/leakages.npy");
+    let dir_p = format!("{folder}/plaintexts.npy");
+    let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
+    let plaintext: Array2<FormatMetadata> = read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
+    let len_traces = leakages.shape()[0];
+
+    // Pattern of 400 samples from the first trace, searched within +/- 50 samples
+    let reference = leakages.row(0).slice(s![..size]).map(|l| *l as f32);
+    let mut align = StaticAlign::new(reference.view(), 1000, 400, 50);
+    align.fft(true);
+    align.min_quality(0.8);
+
+    let mut cpa = Cpa::new(size, patch, guess_range, leakage_model);
+    let mut rejected = 0;
+    for row in (0..len_traces).step_by(patch) {
+        let range_rows = row..(row + patch).min(len_traces);
+        let sample_traces = leakages
+            .slice(s![range_rows.clone(), ..size])
+            .map(|l| *l as f32);
+        let sample_metadata = plaintext.slice(s![range_rows, ..]).map(|p| *p as usize);
+        let (traces, metadata, reports) =
+            align.align_batch(sample_traces.view(), sample_metadata.view());
+        rejected += reports.iter().filter(|r| !r.kept).count();
+        cpa.update(traces, metadata);
+    }
+    println!("Rejected traces = {rejected}");
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        println!(
+            "Guessed key = {} with correlation {}",
+            guess,
+            result.score(guess)
+        );
+    }
+}
+
+fn cpa_elastic() {
+    let size: usize = 5000; // Number of samples
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data/cw");
+    let dir_l = format!("{folder}/leakages.npy");
+    let dir_p = format!("{folder}/plaintexts.npy");
+    let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
+    let plaintext: Array2<FormatMetadata> = read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
+    let len_traces = leakages.shape()[0];
+
+    // Every trace is warped onto the first one, FastDTW radius of 10 samples
+    let reference = leakages.row(0).slice(s![..size]).map(|l| *l as f32);
+    let align = ElasticAlign::new(reference.view(), 10);
+
+    let mut cpa = Cpa::new(size, patch, guess_range, leakage_model);
+    for row in (0..len_traces).step_by(patch) {
+        let range_rows = row..(row + patch).min(len_traces);
+        let sample_traces = leakages
+            .slice(s![range_rows.clone(), ..size])
+            .map(|l| *l as f32);
+        let sample_metadata = plaintext.slice(s![range_rows, ..]).to_owned();
+        cpa.update(align.align_batch(sample_traces.view()), sample_metadata);
+    }
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        println!(
+            "Guessed key = {} with correlation {}",
+            guess,
+            result.score(guess)
+        );
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_aligned();
+    cpa_elastic();
+    println!("{:?}", t.elapsed());
+}
@@ -0,0 +1,47 @@
+use cpa::ascad::{AscadFile, Split};
+use cpa::cpa_normal::*;
+use cpa::leakage::sbox;
+use ndarray::*;
+use std::time::{self};
+
+// leakage model: identity of the sbox output of byte 2, the first masked byte of ASCAD
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    sbox((value[2] ^ guess) as u8) as usize
+}
+
+fn cpa_ascad() {
+    let patch: usize = 1000;
+    let guess_range = 256; // 2**(key length)
+    let target_byte = 2;
+    let file = AscadFile::open("../data/ascad/ASCAD.h5").unwrap();
+    let attack = file.split(Split::Attack).unwrap();
+    let key = attack.known_key().unwrap();
+
+    let mut cpa = Cpa::new(attack.len_samples(), patch, guess_range, leakage_model);
+    cpa.success_traces(patch);
+    if let Some(key) = &key {
+        cpa.known_key(key[target_byte] as i32);
+    }
+    for batch in attack.batches(patch) {
+        let batch = batch.unwrap();
+        // batch.masks holds the masks of every trace for second-order evaluation
+        cpa.update_success(batch.traces, batch.plaintext);
+    }
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        println!(
+            "Guessed key = {} with correlation {}",
+            guess,
+            result.score(guess)
+        );
+    }
+    if let Some(tracker) = cpa.pass_rank_tracker() {
+        println!("Rank of the known key = {:?}", tracker.last_rank());
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_ascad();
+    println!("{:?}", t.elapsed());
+}
@@ -0,0 +1,49 @@
+use cpa::chipwhisperer::CwProject;
+use cpa::cpa_normal::*;
+use cpa::leakage::{hw, sbox};
+use ndarray::*;
+use std::time::{self};
+
+// leakage model
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[1] ^ guess) as u8) as usize)
+}
+
+fn cpa_project() {
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let target_byte = 1;
+    let mut project = CwProject::<f64>::open("../data/cw/project.cwp").unwrap();
+    project.window(0, 5000);
+    println!(
+        "{} traces in {} segments",
+        project.len_traces(),
+        project.segments().len()
+    );
+
+    let mut cpa = Cpa::new(project.len_samples(), patch, guess_range, leakage_model);
+    cpa.success_traces(patch);
+    if let Some(key) = project.known_key() {
+        cpa.known_key(key[target_byte] as i32);
+    }
+    for batch in project.batches(patch) {
+        cpa.update_success(batch.traces.map(|l| *l as f32), batch.plaintext);
+    }
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        println!(
+            "Guessed key = {} with correlation {}",
+            guess,
+            result.score(guess)
+        );
+    }
+    if let Some(tracker) = cpa.pass_rank_tracker() {
+        println!("Rank one after {:?} traces", tracker.traces_to_rank_one());
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_project();
+    println!("{:?}", t.elapsed());
+}
@@ -0,0 +1,77 @@
+use cpa::cpa_normal::*;
+use cpa::leakage::{hw, sbox};
+use cpa::npy::{NpyReader, NpySource};
+use ndarray::*;
+use std::time::{self};
+
+// traces format
+type FormatTraces = f64;
+type FormatMetadata = u8;
+
+// leakage model
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[1] ^ guess) as u8) as usize)
+}
+
+fn cpa_chunked() {
+    let start_sample: usize = 0;
+    let end_sample: usize = 5000;
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data/cw");
+    let dir_l = format!("{folder}/leakages.npy");
+    let dir_p = format!("{folder}/plaintexts.npy");
+    // Both files are mapped, only the batches being processed are read
+    let mut leakages = NpyReader::<FormatTraces>::open(&dir_l).unwrap();
+    leakages.window(start_sample, end_sample);
+    let plaintext = NpyReader::<FormatMetadata>::open(&dir_p).unwrap();
+
+    let mut cpa = Cpa::new(leakages.len_samples(), patch, guess_range, leakage_model);
+    for (sample_traces, sample_metadata) in leakages.chunks(patch).zip(plaintext.chunks(patch)) {
+        let sample_traces = sample_traces.map(|l| *l as f32);
+        cpa.update(sample_traces, sample_metadata.to_owned());
+    }
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        println!(
+            "Guessed key = {} with correlation {}",
+            guess,
+            result.score(guess)
+        );
+    }
+}
+
+fn cpa_any_dtype() {
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data/cw");
+    // The element type, byte order and layout are read from the headers
+    let mut leakages = NpySource::open(&format!("{folder}/leakages.npy")).unwrap();
+    leakages.window(0, 5000);
+    let plaintext = NpySource::open(&format!("{folder}/plaintexts.npy")).unwrap();
+    println!(
+        "traces: {:?}, metadata: {:?}",
+        leakages.dtype(),
+        plaintext.dtype()
+    );
+
+    let mut cpa = Cpa::new(leakages.len_samples(), patch, guess_range, leakage_model);
+    for (sample_traces, sample_metadata) in leakages
+        .batches::<f32>(patch)
+        .zip(plaintext.batches::<usize>(patch))
+        .map(|(traces, metadata)| (traces.unwrap(), metadata.unwrap()))
+    {
+        cpa.update(sample_traces, sample_metadata);
+    }
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        println!("Guessed key = {guess}");
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_chunked();
+    cpa_any_dtype();
+    println!("{:?}", t.elapsed());
+}
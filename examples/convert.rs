@@ -0,0 +1,46 @@
+use cpa::convert::Converter;
+use cpa::npy::{Dtype, NpySource};
+use cpa::trs::{SampleCoding, TrsReader};
+use std::time::{self};
+
+fn convert() {
+    let folder = String::from("../data/trs");
+    // TRS to NPY: samples 0..5000 of every other trace, as f32
+    let mut traces = TrsReader::open(&format!("{folder}/aes.trs")).unwrap();
+    let data = TrsReader::open(&format!("{folder}/aes.trs")).unwrap();
+    let mut converter = Converter::new();
+    converter.crop(0, 5000);
+    converter.traces(0, None, 2);
+    let written = converter
+        .to_npy(
+            &mut traces,
+            Some(&data),
+            &format!("{folder}/leakages.npy"),
+            Some(&format!("{folder}/plaintexts.npy")),
+            Dtype::F32,
+        )
+        .unwrap();
+    println!("{written} traces written to NPY");
+
+    // NPY back to TRS, decimated by 4 and stored on 16 bits, the y scale of
+    // the file mapping the largest sample to 32767
+    let mut leakages = NpySource::open(&format!("{folder}/leakages.npy")).unwrap();
+    let plaintexts = NpySource::open(&format!("{folder}/plaintexts.npy")).unwrap();
+    let mut converter = Converter::new();
+    converter.decimate(4);
+    let written = converter
+        .to_trs(
+            &mut leakages,
+            Some(&plaintexts),
+            &format!("{folder}/aes_decimated.trs"),
+            SampleCoding::Int16,
+        )
+        .unwrap();
+    println!("{written} traces written to TRS");
+}
+
+fn main() {
+    let t = time::Instant::now();
+    convert();
+    println!("{:?}", t.elapsed());
+}
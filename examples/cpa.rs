@@ -1,4 +1,6 @@
+use cpa::convergence::{ConvergenceMonitor, Criterion};
 use cpa::cpa_normal::*;
+use cpa::dataset::Dataset;
 use cpa::leakage::{hw, sbox};
 use cpa::tools::{plot_array2, progress_bar, read_array_2_from_npy_file};
 use indicatif::ProgressIterator;
@@ -31,7 +33,6 @@ fn cpa() {
     let len_traces = leakages.shape()[0];
     let mut cpa_parallel = ((0..len_traces).step_by(patch))
         .progress_with(progress_bar(len_traces))
-        .map(|row| row)
         .par_bridge()
         .map(|row_number| {
             let mut cpa = Cpa::new(size, patch, guess_range, leakage_model);
@@ -49,8 +50,27 @@ fn cpa() {
             || Cpa::new(size, patch, guess_range, leakage_model),
             |x, y| x + y,
         );
-    cpa_parallel.finalize();
-    println!("Guessed key = {}", cpa_parallel.pass_guess());
+    let result = cpa_parallel.finalize();
+    match result.best() {
+        Some(guess) => println!(
+            "Guessed key = {} (sample {}, margin {})",
+            guess,
+            result.peak(guess),
+            result.margins()[0]
+        ),
+        None => println!("No correlation found"),
+    }
+    println!("Top 5 guesses = {:?}", result.top_k(5));
+    let guess = result.ranking()[0];
+    let significance = result.significance();
+    let (lower, upper) = significance.interval(0.95);
+    println!(
+        "95% interval = [{}, {}], p-value = {:e}, traces needed = {:.0}",
+        lower[guess],
+        upper[guess],
+        significance.p_values()[guess],
+        significance.min_traces(0.9999)[guess]
+    );
     let corr = cpa_parallel.pass_corr_array();
     let plot: Plot = plot_array2(corr.clone(), String::from("K"), String::from("CPA of K[0]"));
     plot.show();
@@ -65,29 +85,29 @@ fn success() {
     let patch: usize = 500;
     let guess_range = 256; // 2**(key length)
     let folder = String::from("../data/log_584012"); // "../../../intenship/scripts/log_584012"
-    let nfiles = 13; // Number of files in the directory. TBD: Automating this value
+    let mut dataset =
+        Dataset::<FormatTraces, FormatMetadata>::discover(&folder, "l/{i}.npy", "p/{i}.npy")
+            .unwrap();
+    dataset.window(start_sample, end_sample);
     let rank_traces: usize = 1000;
+    let correct_key = 0x2b; // Known key byte of the campaign
     let mut cpa = Cpa::new(size, patch, guess_range, leakage_model);
     cpa.success_traces(rank_traces);
-    for i in (0..nfiles).progress() {
-        let dir_l = format!("{folder}/l/{i}.npy");
-        let dir_p = format!("{folder}/p/{i}.npy");
-        let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
-        let plaintext: Array2<FormatMetadata> =
-            read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
-        let len_leakages = leakages.shape()[0];
-        for row in (0..len_leakages).step_by(patch) {
-            let range_samples = start_sample..end_sample;
-            let range_rows: std::ops::Range<usize> = row..row + patch;
-            let range_metadat = 0..plaintext.shape()[1];
-            let sample_traces = leakages
-                .slice(s![range_rows.clone(), range_samples])
-                .map(|l| *l as f32);
-            let sample_metadata: Array2<FormatMetadata> =
-                plaintext.slice(s![range_rows, range_metadat]).to_owned();
-            cpa.update_success(sample_traces, sample_metadata);
+    cpa.known_key(correct_key);
+    // stop once the guess is stable for 3 checkpoints and significant at 1e-3
+    cpa.early_stopping(ConvergenceMonitor::new(3, Criterion::Significance(1e-3)));
+    for (sample_traces, sample_metadata) in dataset
+        .batches(patch)
+        .progress_count(dataset.len_traces().div_ceil(patch) as u64)
+    {
+        cpa.update_success(sample_traces.map(|l| *l as f32), sample_metadata);
+        if cpa.converged() {
+            break;
         }
     }
+    if let Some(traces_no) = cpa.pass_monitor().and_then(|m| m.stopped_at()) {
+        println!("Converged after {traces_no} traces");
+    }
     cpa.finalize();
     println!("Guessed key = {}", cpa.pass_guess());
     // save corr key curves in npy
@@ -98,6 +118,10 @@ fn success() {
     );
     plot.show();
     // write_array("results/success.npy", cpa.pass_rank().view());
+    let tracker = cpa.pass_rank_tracker().unwrap();
+    println!("Rank one after {:?} traces", tracker.traces_to_rank_one());
+    tracker.plot_rank().show();
+    // tracker.write_csv("results/rank.csv");
 }
 
 fn main() {
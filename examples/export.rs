@@ -0,0 +1,52 @@
+use cpa::cpa_normal::*;
+use cpa::dataset::Dataset;
+use cpa::export::Export;
+use cpa::leakage::{hw, sbox};
+use ndarray::*;
+use std::time::{self};
+
+// traces format
+type FormatTraces = f64;
+type FormatMetadata = u8;
+
+// leakage model on the plaintext byte given to the engine
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[0] ^ guess) as u8) as usize)
+}
+
+fn cpa_export() {
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data");
+    let mut dataset =
+        Dataset::<FormatTraces, FormatMetadata>::discover(&folder, "l/{i}.npy", "p/{i}.npy")
+            .unwrap();
+    dataset.window(0, 5000);
+
+    let mut export = Export::new();
+    export.config("folder", folder.clone());
+    export.config("patch", patch);
+    export.config("model", "hw(sbox(p ^ k))");
+    export.traces(dataset.len_traces());
+    for byte in 0..dataset.len_metadata() {
+        let t = time::Instant::now();
+        let mut cpa = Cpa::new(dataset.len_samples(), patch, guess_range, leakage_model);
+        for (sample_traces, sample_metadata) in dataset.batches(patch) {
+            let plaintext = sample_metadata.slice(s![.., byte..byte + 1]).to_owned();
+            cpa.update(sample_traces.map(|l| *l as f32), plaintext);
+        }
+        let result = cpa.finalize();
+        export.result(byte, &result);
+        export.correlation(byte, cpa.pass_corr_array().view());
+        export.timing(&format!("byte {byte}"), t.elapsed());
+    }
+    println!("Guessed key = {:?}", export.guesses());
+    export.write_npz("results/attack.npz").unwrap();
+    export.write_json("results/attack.json").unwrap();
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_export();
+    println!("{:?}", t.elapsed());
+}
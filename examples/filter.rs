@@ -0,0 +1,66 @@
+use cpa::cpa_normal::*;
+use cpa::filter::{DcRemoval, Fir, Iir};
+use cpa::leakage::{hw, sbox};
+use cpa::pipeline::{Pipeline, Stage};
+use cpa::tools::read_array_2_from_npy_file;
+use ndarray::*;
+use std::time::{self};
+
+// traces format
+type FormatTraces = f64;
+type FormatMetadata = u8;
+
+// leakage model
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[1] ^ guess) as u8) as usize)
+}
+
+fn cpa_filtered() {
+    let size: usize = 5000; // Number of samples
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data/cw");
+    let dir_l = format!("{folder}/leakages.npy");
+    let dir_p = format!("{folder}/plaintexts.npy");
+    let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
+    let plaintext: Array2<FormatMetadata> = read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
+    let len_traces = leakages.shape()[0];
+
+    // Baseline removal, then a band-pass FIR and a zero-phase Butterworth low-pass
+    let mut pipeline = Pipeline::new();
+    pipeline.add(DcRemoval::Moving(501));
+    pipeline.add(Fir::band_pass(0.01, 0.2, 101));
+    let mut low_pass = Iir::butterworth_low_pass(4, 0.15);
+    low_pass.zero_phase(true);
+    pipeline.add(low_pass);
+
+    let mut cpa = Cpa::new(
+        pipeline.len_samples(size),
+        patch,
+        guess_range,
+        leakage_model,
+    );
+    for row in (0..len_traces).step_by(patch) {
+        let range_rows = row..(row + patch).min(len_traces);
+        let sample_traces = leakages
+            .slice(s![range_rows.clone(), ..size])
+            .map(|l| *l as f32);
+        let sample_metadata = plaintext.slice(s![range_rows, ..]).map(|p| *p as usize);
+        let (traces, metadata) = pipeline.process(sample_traces, sample_metadata);
+        cpa.update(traces, metadata);
+    }
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        println!(
+            "Guessed key = {} with correlation {}",
+            guess,
+            result.score(guess)
+        );
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_filtered();
+    println!("{:?}", t.elapsed());
+}
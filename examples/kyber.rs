@@ -0,0 +1,58 @@
+use cpa::cpa_normal::*;
+use cpa::pqc::{
+    assemble_polynomial, center_polynomial, kyber_fqmul_hw, kyber_inverse_ntt, KYBER_Q,
+};
+use cpa::tools::read_array_2_from_npy_file;
+use indicatif::ProgressIterator;
+use ndarray::*;
+use std::time::{self};
+
+// traces format
+type FormatTraces = f64;
+type FormatMetadata = u16;
+
+// leakage model: fqmul of the known ciphertext coefficient with the secret one
+pub fn leakage_model(
+    coefficient: usize,
+) -> impl Fn(ArrayView1<usize>, usize) -> usize + Send + Sync {
+    move |value, guess| kyber_fqmul_hw(value[coefficient], guess)
+}
+
+fn cpa_kyber() {
+    let start_sample: usize = 0;
+    let end_sample: usize = 1000;
+    let size: usize = end_sample - start_sample; // Number of samples
+    let patch: usize = 500;
+    let guess_range = KYBER_Q; // secret NTT coefficient in 0..q
+    let n_coefficients = 256;
+    let folder = String::from("../data/kyber");
+    let dir_l = format!("{folder}/leakages.npy");
+    let dir_p = format!("{folder}/ciphertexts.npy");
+    let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
+    let ciphertext: Array2<FormatMetadata> = read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
+    let len_traces = leakages.shape()[0];
+    let mut guesses = Vec::new();
+    for coefficient in (0..n_coefficients).progress() {
+        let mut cpa = Cpa::new(size, patch, guess_range, leakage_model(coefficient));
+        for row in (0..len_traces).step_by(patch) {
+            let range_rows = row..row + patch;
+            let sample_traces = leakages
+                .slice(s![range_rows.clone(), start_sample..end_sample])
+                .map(|l| *l as f32);
+            let sample_metadata = ciphertext.slice(s![range_rows, ..]).to_owned();
+            cpa.update(sample_traces, sample_metadata);
+        }
+        cpa.finalize();
+        guesses.push(cpa.pass_guess());
+    }
+    // the secret is kept in the NTT domain, bring it back to the normal domain
+    let secret_ntt = assemble_polynomial(&guesses, KYBER_Q, 0);
+    let secret = center_polynomial(&kyber_inverse_ntt(&secret_ntt), KYBER_Q);
+    println!("Guessed secret = {:?}", secret);
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_kyber();
+    println!("{:?}", t.elapsed());
+}
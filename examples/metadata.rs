@@ -0,0 +1,52 @@
+use cpa::cpa_normal::*;
+use cpa::leakage::{hw, sbox};
+use cpa::metadata::{ByteFormat, Column, MetadataCsv};
+use cpa::npy::NpyReader;
+use ndarray::*;
+use std::time::{self};
+
+// leakage model
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[1] ^ guess) as u8) as usize)
+}
+
+fn cpa_csv() {
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data/cw");
+    let leakages = NpyReader::<f64>::open(&format!("{folder}/leakages.npy")).unwrap();
+    // Lines "index,plaintext,ciphertext" logged by the harness
+    let mut csv = MetadataCsv::new(ByteFormat::Hex);
+    csv.traces(leakages.len_traces());
+    csv.header(true);
+    csv.index(Column::Name("index".into()));
+    csv.plaintext(Some(Column::Name("plaintext".into())));
+    csv.ciphertext(Some(Column::Name("ciphertext".into())));
+    let metadata = csv.read(&format!("{folder}/harness.csv")).unwrap();
+    for line in metadata.malformed() {
+        println!("line {}: {}", line.line, line.message);
+    }
+    println!("{} traces without metadata", metadata.missing().len());
+    metadata
+        .write_npy_files(&format!("{folder}/p{{i}}.npy"), &[metadata.len_traces()])
+        .unwrap();
+
+    let mut cpa = Cpa::new(leakages.len_samples(), patch, guess_range, leakage_model);
+    for (sample_traces, sample_metadata) in leakages.chunks(patch).zip(metadata.batches(patch)) {
+        cpa.update(sample_traces.map(|l| *l as f32), sample_metadata);
+    }
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        println!(
+            "Guessed key = {} with correlation {}",
+            guess,
+            result.score(guess)
+        );
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_csv();
+    println!("{:?}", t.elapsed());
+}
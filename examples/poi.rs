@@ -0,0 +1,55 @@
+use cpa::cpa_normal::*;
+use cpa::leakage::{hw, sbox};
+use cpa::poi::{Poi, Selection, Snr};
+use cpa::tools::read_array_2_from_npy_file;
+use ndarray::*;
+use std::time::{self};
+
+// traces format
+type FormatTraces = f64;
+type FormatMetadata = u8;
+
+// leakage model
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[1] ^ guess) as u8) as usize)
+}
+
+fn cpa_poi() {
+    let size: usize = 5000; // Number of samples
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data/cw");
+    let dir_l = format!("{folder}/leakages.npy");
+    let dir_p = format!("{folder}/plaintexts.npy");
+    let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
+    let plaintext: Array2<FormatMetadata> = read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
+    let len_traces = leakages.shape()[0];
+
+    // SNR on the plaintext byte, the sbox input is key dependent but its
+    // classes are the same up to a permutation
+    let mut snr = Snr::new(size, 256);
+    let labels = plaintext.column(1).map(|p| *p as usize);
+    snr.update(leakages.slice(s![.., ..size]), labels.view());
+    let poi = Poi::select(snr.snr().view(), Selection::TopNSpaced(50, 5));
+
+    let mut cpa = Cpa::new(poi.len(), patch, guess_range, leakage_model);
+    for row in (0..len_traces).step_by(patch) {
+        let range_rows = row..(row + patch).min(len_traces);
+        let sample_traces = poi
+            .apply(leakages.slice(s![range_rows.clone(), ..]))
+            .map(|l| *l as f32);
+        let sample_metadata = plaintext.slice(s![range_rows, ..]).to_owned();
+        cpa.update(sample_traces, sample_metadata);
+    }
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        let sample = poi.original_index(result.peak(guess));
+        println!("Guessed key = {} at sample {}", guess, sample);
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_poi();
+    println!("{:?}", t.elapsed());
+}
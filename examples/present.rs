@@ -0,0 +1,49 @@
+use cpa::cpa_normal::*;
+use cpa::leakage::{combine_nibbles, hw, nibble, to_word};
+use cpa::present;
+use cpa::tools::read_array_2_from_npy_file;
+use indicatif::ProgressIterator;
+use ndarray::*;
+use std::time::{self};
+
+// traces format
+type FormatTraces = f64;
+type FormatMetadata = u8;
+
+// leakage model: output of the first round sbox of PRESENT on nibble `target`
+pub fn leakage_model(target: usize) -> impl Fn(ArrayView1<usize>, usize) -> usize + Send + Sync {
+    move |value, guess| {
+        let nibble = nibble(to_word(value), target);
+        hw(present::sbox((nibble ^ guess) as u8) as usize)
+    }
+}
+
+fn cpa_present() {
+    let start_sample: usize = 0;
+    let end_sample: usize = 2000;
+    let size: usize = end_sample - start_sample; // Number of samples
+    let guess_range = 16; // 2**(nibble length)
+    let folder = String::from("../data/present");
+    let dir_l = format!("{folder}/leakages.npy");
+    let dir_p = format!("{folder}/plaintexts.npy");
+    let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
+    let plaintext: Array2<FormatMetadata> = read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
+    let len_traces = leakages.shape()[0];
+    let mut guesses = Vec::new();
+    for target in (0..16).progress() {
+        let mut cpa = Cpa::new(size, len_traces, guess_range, leakage_model(target));
+        let sample_traces = leakages
+            .slice(s![.., start_sample..end_sample])
+            .map(|l| *l as f32);
+        cpa.update(sample_traces, plaintext.clone());
+        cpa.finalize();
+        guesses.push(cpa.pass_guess());
+    }
+    println!("Guessed round key = {:016x}", combine_nibbles(&guesses));
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_present();
+    println!("{:?}", t.elapsed());
+}
@@ -0,0 +1,62 @@
+use cpa::cpa_normal::*;
+use cpa::leakage::{hw, sbox};
+use cpa::pipeline::Stage;
+use cpa::reject::OutlierFilter;
+use cpa::tools::read_array_2_from_npy_file;
+use ndarray::*;
+use std::time::{self};
+
+// traces format
+type FormatTraces = f64;
+type FormatMetadata = u8;
+
+// leakage model
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[1] ^ guess) as u8) as usize)
+}
+
+fn cpa_rejection() {
+    let size: usize = 5000; // Number of samples
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data/cw");
+    let dir_l = format!("{folder}/leakages.npy");
+    let dir_p = format!("{folder}/plaintexts.npy");
+    let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
+    let plaintext: Array2<FormatMetadata> = read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
+    let len_traces = leakages.shape()[0];
+
+    // z-score above 8 after 500 traces, or more than 10 samples at the ADC limits
+    let mut outliers = OutlierFilter::new(size, patch);
+    outliers.z_score(8.0);
+    outliers.clipping(-0.5, 0.5, 10);
+
+    let mut cpa = Cpa::new(size, patch, guess_range, leakage_model);
+    for row in (0..len_traces).step_by(patch) {
+        let range_rows = row..(row + patch).min(len_traces);
+        let sample_traces = leakages
+            .slice(s![range_rows.clone(), ..size])
+            .map(|l| *l as f32);
+        let sample_metadata = plaintext.slice(s![range_rows, ..]).map(|p| *p as usize);
+        let (traces, metadata) = outliers.process(sample_traces, sample_metadata);
+        cpa.update(traces, metadata);
+    }
+    let mut result = cpa.finalize();
+    result.rejected_traces(outliers.rejected());
+    for rejection in result.rejected() {
+        println!("Rejected trace {}: {:?}", rejection.index, rejection.reason);
+    }
+    if let Some(guess) = result.best() {
+        println!(
+            "Guessed key = {} with correlation {}",
+            guess,
+            result.score(guess)
+        );
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_rejection();
+    println!("{:?}", t.elapsed());
+}
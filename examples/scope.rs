@@ -0,0 +1,54 @@
+use cpa::cpa_normal::*;
+use cpa::dataset::SourceDataset;
+use cpa::leakage::{hw, sbox};
+use cpa::scope::Waveforms;
+use ndarray::*;
+use std::time::{self};
+
+// leakage model
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[1] ^ guess) as u8) as usize)
+}
+
+fn cpa_scope() {
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data/scope");
+    let nfiles = 10; // Sequence mode captures, one .trc file per acquisition
+    let files: Vec<String> = (0..nfiles)
+        .map(|i| format!("{folder}/C1--{i:05}.trc"))
+        .collect();
+    let waveforms = Waveforms::open(&files).unwrap();
+    if let Some(header) = waveforms.header() {
+        println!(
+            "{} traces of {} samples, {} V/LSB, {} s/sample",
+            waveforms.len_traces(),
+            waveforms.len_samples(),
+            header.vertical_gain,
+            header.horizontal_interval
+        );
+    }
+    // Plaintexts of all the segments, in acquisition order
+    let mut dataset =
+        SourceDataset::<_, u8>::new(waveforms, &format!("{folder}/plaintexts.npy")).unwrap();
+    dataset.window(0, 5000);
+
+    let mut cpa = Cpa::new(dataset.len_samples(), patch, guess_range, leakage_model);
+    for (sample_traces, sample_metadata) in dataset.batches(patch) {
+        cpa.update(sample_traces, sample_metadata);
+    }
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        println!(
+            "Guessed key = {} with correlation {}",
+            guess,
+            result.score(guess)
+        );
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_scope();
+    println!("{:?}", t.elapsed());
+}
@@ -1,6 +1,7 @@
 use cpa::cpa_single::*;
+use cpa::dataset::Dataset;
 use cpa::leakage::{hw, sbox};
-use cpa::tools::{read_array_2_from_npy_file, write_array};
+use cpa::tools::write_array;
 use indicatif::ProgressIterator;
 use ndarray::*;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
@@ -13,7 +14,7 @@ static mut TARGET_BYTE: usize = 0;
 // leakage model
 
 pub fn leakage_model(value: Array1<FormatMetadata>, guess: usize) -> f64 {
-    unsafe { hw(sbox(value[TARGET_BYTE] as u8 ^ guess as u8) as usize) as f64 }
+    unsafe { hw(sbox(value[TARGET_BYTE] ^ guess as u8) as usize) as f64 }
 }
 
 #[allow(dead_code)]
@@ -23,36 +24,29 @@ fn sucess_rate_cw() {
     let size: usize = end_sample - start_sample; // Number of samples
     let guess_range = 256; // 2**(key length)
     let folder = String::from("../data/log_cw"); // Directory of leakages and metadata
-    let nfiles: i32 = 5; // Number of files in the directory. TBD: Automating this value
+    let mut dataset =
+        Dataset::<FormatTraces, FormatMetadata>::discover(&folder, "l/{i}.npy", "p/{i}.npy")
+            .unwrap();
+    dataset.window(start_sample, end_sample);
     let success_no = 500;
     let mut cpa = Cpa::new(size, guess_range, leakage_model);
-    for n_files in (0..nfiles).progress() {
-        let dir_l = format!("{folder}/l/{n_files}.npy");
-        let dir_p = format!("{folder}/p/{n_files}.npy");
-        let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
-        let plaintext: Array2<FormatMetadata> =
-            read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
-        let no_traces = leakages.shape()[0];
-        for block in (0..no_traces).step_by(success_no) {
-            let l_chunk: ArrayView2<FormatTraces> =
-                leakages.slice(s![block..block + success_no, start_sample..end_sample]);
-            let p_chunk: ArrayView2<FormatMetadata> =
-                plaintext.slice(s![block..block + success_no, ..]);
-            let cpa_inner = (0..success_no)
-                .into_par_iter()
-                .map(|index| {
-                    let mut c = Cpa::new(size, guess_range, leakage_model);
-                    c.update(l_chunk.row(index).to_owned(), p_chunk.row(index).to_owned());
-                    c
-                })
-                .reduce(|| Cpa::new(size, guess_range, leakage_model), |x, y| x + y);
-            cpa = cpa + cpa_inner;
-            cpa.finalize();
-            cpa.update_success();
-        }
-
-        write_array("../results/success.npy", cpa.pass_succes().view());
+    for (l_chunk, p_chunk) in dataset
+        .batches(success_no)
+        .progress_count(dataset.len_traces().div_ceil(success_no) as u64)
+    {
+        let cpa_inner = (0..l_chunk.shape()[0])
+            .into_par_iter()
+            .map(|index| {
+                let mut c = Cpa::new(size, guess_range, leakage_model);
+                c.update(l_chunk.row(index).to_owned(), p_chunk.row(index).to_owned());
+                c
+            })
+            .reduce(|| Cpa::new(size, guess_range, leakage_model), |x, y| x + y);
+        cpa = cpa + cpa_inner;
+        cpa.finalize();
+        cpa.update_success();
     }
+    write_array("../results/success.npy", cpa.pass_succes().view());
 }
 
 pub fn cpa_cw() {
@@ -65,23 +59,19 @@ pub fn cpa_cw() {
             let size: usize = end_sample - start_sample; // Number of samples
             let guess_range = 256; // 2**(key length)
             let folder = String::from("../../../intenship/scripts/log_584012"); // ../data/log_cw
-            let nfiles: i32 = 13; // Number of files in the directory. TBD: Automating this value
-            let mut cpa_parallel: Cpa<ArrayBase<OwnedRepr<u8>, Dim<[usize; 1]>>> = (0..nfiles)
-                .into_par_iter()
-                .map(|num| {
-                    let dir_l: String = format!("{folder}/l/{num}.npy");
-                    let dir_p = format!("{folder}/p/{num}.npy");
-                    let leakages: Array2<FormatTraces> =
-                        read_array_2_from_npy_file::<FormatTraces>(&dir_l);
-                    let plaintext: Array2<FormatMetadata> =
-                        read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
-                    let len_leakages = leakages.shape()[0];
+            let mut dataset = Dataset::<FormatTraces, FormatMetadata>::discover(
+                &folder,
+                "l/{i}.npy",
+                "p/{i}.npy",
+            )
+            .unwrap();
+            dataset.window(start_sample, end_sample);
+            let mut cpa_parallel: Cpa<ArrayBase<OwnedRepr<u8>, Dim<[usize; 1]>>> = dataset
+                .par_batches(1000)
+                .map(|(leakages, plaintext)| {
                     let mut cpa = Cpa::new(size, guess_range, leakage_model);
-                    for row in 0..len_leakages {
-                        let sample_trace: Array1<f64> = leakages
-                            .row(row)
-                            .slice(s![start_sample..end_sample])
-                            .to_owned();
+                    for row in 0..leakages.shape()[0] {
+                        let sample_trace: Array1<f64> = leakages.row(row).to_owned();
                         let sample_metadat: Array1<FormatMetadata> = plaintext.row(row).to_owned();
                         cpa.update(sample_trace, sample_metadat);
                     }
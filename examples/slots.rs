@@ -0,0 +1,65 @@
+use cpa::compress::Reduction;
+use cpa::cpa_normal::*;
+use cpa::leakage::{hw, sbox};
+use cpa::pipeline::{Pipeline, Stage};
+use cpa::slots::{SlidingWindow, SlotCombine};
+use cpa::tools::read_array_2_from_npy_file;
+use ndarray::*;
+use std::time::{self};
+
+// traces format
+type FormatTraces = f64;
+type FormatMetadata = u8;
+
+// leakage model
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[1] ^ guess) as u8) as usize)
+}
+
+fn cpa_shuffled() {
+    let size: usize = 5000; // Number of samples
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data/cw");
+    let dir_l = format!("{folder}/leakages.npy");
+    let dir_p = format!("{folder}/plaintexts.npy");
+    let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
+    let plaintext: Array2<FormatMetadata> = read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
+    let len_traces = leakages.shape()[0];
+
+    // The 16 sbox computations run in random order in slots of 200 samples
+    // starting at sample 1000, small jitters are absorbed by a 5-sample window
+    let mut pipeline = Pipeline::new();
+    pipeline.add(SlotCombine::periodic(1000, 200, 16, Reduction::Sum));
+    pipeline.add(SlidingWindow::new(5, Reduction::Mean));
+
+    let mut cpa = Cpa::new(
+        pipeline.len_samples(size),
+        patch,
+        guess_range,
+        leakage_model,
+    );
+    for row in (0..len_traces).step_by(patch) {
+        let range_rows = row..(row + patch).min(len_traces);
+        let sample_traces = leakages
+            .slice(s![range_rows.clone(), ..size])
+            .map(|l| *l as f32);
+        let sample_metadata = plaintext.slice(s![range_rows, ..]).map(|p| *p as usize);
+        let (traces, metadata) = pipeline.process(sample_traces, sample_metadata);
+        cpa.update(traces, metadata);
+    }
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        println!(
+            "Guessed key = {} at slot sample {}",
+            guess,
+            result.peak(guess)
+        );
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_shuffled();
+    println!("{:?}", t.elapsed());
+}
@@ -0,0 +1,68 @@
+use cpa::cpa_normal::*;
+use cpa::leakage::{hw, sbox};
+use cpa::pipeline::{Pipeline, Stage};
+use cpa::spectrum::{Spectrum, Window};
+use cpa::tools::read_array_2_from_npy_file;
+use ndarray::*;
+use std::time::{self};
+
+// traces format
+type FormatTraces = f64;
+type FormatMetadata = u8;
+
+// leakage model
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[1] ^ guess) as u8) as usize)
+}
+
+fn cpa_spectrum() {
+    let size: usize = 5000; // Number of samples
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let sample_rate = 29.48e6; // Sampling rate in Hz
+    let folder = String::from("../data/cw");
+    let dir_l = format!("{folder}/leakages.npy");
+    let dir_p = format!("{folder}/plaintexts.npy");
+    let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
+    let plaintext: Array2<FormatMetadata> = read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
+    let len_traces = leakages.shape()[0];
+
+    // Hann windows of 256 samples overlapping by half
+    let mut spectrum = Spectrum::new(256, Window::Hann);
+    spectrum.step(128);
+    let mut pipeline = Pipeline::new();
+    pipeline.add(spectrum.clone());
+
+    let mut cpa = Cpa::new(
+        pipeline.len_samples(size),
+        patch,
+        guess_range,
+        leakage_model,
+    );
+    for row in (0..len_traces).step_by(patch) {
+        let range_rows = row..(row + patch).min(len_traces);
+        let sample_traces = leakages
+            .slice(s![range_rows.clone(), ..size])
+            .map(|l| *l as f32);
+        let sample_metadata = plaintext.slice(s![range_rows, ..]).map(|p| *p as usize);
+        let (traces, metadata) = pipeline.process(sample_traces, sample_metadata);
+        cpa.update(traces, metadata);
+    }
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        let peak = result.peak(guess);
+        let (start, _) = spectrum.locate(peak);
+        println!(
+            "Guessed key = {} at {:.0} Hz in the window starting at sample {}",
+            guess,
+            spectrum.frequency(peak, sample_rate),
+            start
+        );
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_spectrum();
+    println!("{:?}", t.elapsed());
+}
@@ -0,0 +1,56 @@
+use cpa::cpa_tiled::*;
+use cpa::leakage::hw;
+use cpa::tools::{read_array_2_from_npy_file, write_array};
+use ndarray::*;
+use std::time::{self};
+
+// traces format
+type FormatTraces = f64;
+type FormatMetadata = u8;
+
+// leakage model: IDEA multiplication of a 16-bit plaintext word with a 16-bit key
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    let word = (value[0] << 8) | value[1];
+    let a = if word == 0 { 0x10000 } else { word };
+    let b = if guess == 0 { 0x10000 } else { guess };
+    hw((a * b % 0x10001) & 0xFFFF)
+}
+
+fn cpa_tiled() {
+    let start_sample: usize = 0;
+    let end_sample: usize = 1000;
+    let size: usize = end_sample - start_sample; // Number of samples
+    let patch: usize = 500;
+    let guess_range: usize = 1 << 16; // 2**(key length)
+    let tile: usize = 4096; // Guesses held in memory at once
+    let folder = String::from("../data/idea");
+    let dir_l = format!("{folder}/leakages.npy");
+    let dir_p = format!("{folder}/plaintexts.npy");
+    let leakages: Array2<FormatTraces> = read_array_2_from_npy_file::<FormatTraces>(&dir_l);
+    let plaintext: Array2<FormatMetadata> = read_array_2_from_npy_file::<FormatMetadata>(&dir_p);
+    let len_traces = leakages.shape()[0];
+    let mut cpa = Cpa_tiled::new(size, patch, guess_range, tile, leakage_model);
+    cpa.tiles_per_pass(4); // Reads the campaign 4 times instead of 16
+    cpa.run(|| {
+        (0..len_traces).step_by(patch).map(|row| {
+            let range_rows = row..(row + patch).min(len_traces);
+            let sample_traces = leakages
+                .slice(s![range_rows.clone(), start_sample..end_sample])
+                .map(|l| *l as f32);
+            let sample_metadata = plaintext.slice(s![range_rows, ..]).to_owned();
+            (sample_traces, sample_metadata)
+        })
+    });
+    match cpa.pass_guess() {
+        Some(guess) => println!("Guessed key = {guess:04x}"),
+        None => println!("No guess correlates with the traces"),
+    }
+    println!("Top 5 guesses = {:?}", cpa.pass_result().top_k(5));
+    write_array("../results/max_corr.npy", cpa.pass_max_corr());
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_tiled();
+    println!("{:?}", t.elapsed());
+}
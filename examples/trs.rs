@@ -0,0 +1,60 @@
+use cpa::cpa_normal::*;
+use cpa::filter::Fir;
+use cpa::leakage::{hw, sbox};
+use cpa::pipeline::Stage;
+use cpa::trs::{SampleCoding, TrsReader, TrsWriter};
+use ndarray::*;
+use std::time::{self};
+
+// leakage model
+pub fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+    hw(sbox((value[1] ^ guess) as u8) as usize)
+}
+
+fn cpa_trs() {
+    let patch: usize = 500;
+    let guess_range = 256; // 2**(key length)
+    let folder = String::from("../data/trs");
+    // The data bytes of every trace hold the plaintext followed by the ciphertext
+    let traces = TrsReader::open(&format!("{folder}/aes.trs")).unwrap();
+    let header = traces.header();
+    println!(
+        "{} traces of {} samples, {} data bytes",
+        header.traces_no, header.len_samples, header.data_len
+    );
+
+    // Low-pass filtered copy of the trace set
+    let mut low_pass = Fir::low_pass(0.1, 51);
+    let mut writer = TrsWriter::create(
+        &format!("{folder}/aes_filtered.trs"),
+        traces.len_samples(),
+        header.data_len,
+        SampleCoding::Float32,
+    )
+    .unwrap();
+
+    let mut cpa = Cpa::new(traces.len_samples(), patch, guess_range, leakage_model);
+    for (sample_traces, sample_data) in traces.batches(patch) {
+        let (filtered, metadata) =
+            low_pass.process(sample_traces, sample_data.map(|d| *d as usize));
+        writer
+            .write_batch(filtered.view(), sample_data.view())
+            .unwrap();
+        cpa.update(filtered, metadata);
+    }
+    writer.finish().unwrap();
+    let result = cpa.finalize();
+    if let Some(guess) = result.best() {
+        println!(
+            "Guessed key = {} with correlation {}",
+            guess,
+            result.score(guess)
+        );
+    }
+}
+
+fn main() {
+    let t = time::Instant::now();
+    cpa_trs();
+    println!("{:?}", t.elapsed());
+}
@@ -0,0 +1,389 @@
+use crate::fft::{cross_correlation, real_fft};
+use crate::pipeline::Stage;
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2, Axis};
+use num_complex::Complex64;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+/* Static alignment: a reference window cut from a reference trace is searched
+in every trace within +/- max_shift samples of its original position, the
+shift maximising the normalised cross-correlation (NCC) is applied to the
+whole trace. Samples shifted in from outside the trace repeat the edge
+sample. The NCC is computed directly or through the FFT, which is faster for
+long windows and large search ranges. */
+
+#[derive(Clone, Copy, Debug)]
+pub struct AlignReport {
+    /// Offset of the reference window in the trace, the aligned trace is
+    /// trace[i + shift].
+    pub shift: isize,
+    /// Normalised cross-correlation at the selected shift, in [-1, 1].
+    pub quality: f32,
+    /// false when the quality is below the threshold, or when the trace is
+    /// too short to hold the reference window in the search range (quality
+    /// is then NaN).
+    pub kept: bool,
+}
+
+pub struct StaticAlign {
+    pattern: Vec<f64>,
+    pattern_norm: f64,
+    ref_start: usize,
+    max_shift: usize,
+    use_fft: bool,
+    min_quality: f32,
+}
+
+impl StaticAlign {
+    /// Uses `reference[ref_start..ref_start + ref_len]` as the pattern and
+    /// searches it in a range of +/- `max_shift` samples.
+    pub fn new(
+        reference: ArrayView1<f32>,
+        ref_start: usize,
+        ref_len: usize,
+        max_shift: usize,
+    ) -> Self {
+        assert!(
+            ref_len > 0 && ref_start + ref_len <= reference.len(),
+            "reference window {ref_start}..{} is empty or outside the reference trace",
+            ref_start + ref_len
+        );
+        let window: Vec<f64> = (ref_start..ref_start + ref_len)
+            .map(|i| reference[i] as f64)
+            .collect();
+        let mean = window.iter().sum::<f64>() / ref_len as f64;
+        let pattern: Vec<f64> = window.iter().map(|x| x - mean).collect();
+        let pattern_norm = pattern.iter().map(|x| x * x).sum::<f64>().sqrt();
+        assert!(
+            pattern_norm > 0.0,
+            "reference window {ref_start}..{} is constant",
+            ref_start + ref_len
+        );
+        Self {
+            pattern,
+            pattern_norm,
+            ref_start,
+            max_shift,
+            use_fft: false,
+            min_quality: f32::NEG_INFINITY,
+        }
+    }
+
+    /// Computes the cross-correlation through the FFT.
+    pub fn fft(&mut self, use_fft: bool) {
+        self.use_fft = use_fft;
+    }
+
+    /// Traces whose best NCC is below `quality` are rejected by align_batch.
+    pub fn min_quality(&mut self, quality: f32) {
+        self.min_quality = quality;
+    }
+
+    pub fn find_shift(&self, trace: ArrayView1<f32>) -> AlignReport {
+        let len_pattern = self.pattern.len();
+        let first = self.ref_start.saturating_sub(self.max_shift);
+        if trace.len() < len_pattern || trace.len() - len_pattern < first {
+            return AlignReport {
+                shift: 0,
+                quality: f32::NAN,
+                kept: false,
+            };
+        }
+        let last = (self.ref_start + self.max_shift).min(trace.len() - len_pattern);
+        let segment: Vec<f64> = (first..last + len_pattern)
+            .map(|i| trace[i] as f64)
+            .collect();
+        let len_shifts = last - first + 1;
+
+        /* Numerators sum(x[j + k] * p[j]), the pattern being zero-mean the
+        mean of the trace window does not need to be removed */
+        let numerators: Vec<f64> = if self.use_fft {
+            let n = segment.len().next_power_of_two();
+            let pattern_spectrum: Vec<Complex64> = real_fft(&self.pattern, n);
+            cross_correlation(&segment, &pattern_spectrum)
+        } else {
+            (0..len_shifts)
+                .map(|k| {
+                    self.pattern
+                        .iter()
+                        .zip(&segment[k..])
+                        .map(|(p, x)| p * x)
+                        .sum()
+                })
+                .collect()
+        };
+
+        /* Norm of every zero-mean trace window from prefix sums */
+        let mut sum = vec![0.0; segment.len() + 1];
+        let mut sum2 = vec![0.0; segment.len() + 1];
+        for (i, x) in segment.iter().enumerate() {
+            sum[i + 1] = sum[i] + x;
+            sum2[i + 1] = sum2[i] + x * x;
+        }
+
+        let mut best = AlignReport {
+            shift: 0,
+            quality: f32::NEG_INFINITY,
+            kept: false,
+        };
+        for k in 0..len_shifts {
+            let s = sum[k + len_pattern] - sum[k];
+            let s2 = sum2[k + len_pattern] - sum2[k];
+            let variance = s2 - s * s / len_pattern as f64;
+            /* A flat window does not correlate with the pattern, its variance
+            is only the round-off of the prefix sums */
+            let quality = if variance <= f64::EPSILON * len_pattern as f64 * s2 {
+                0.0
+            } else {
+                (numerators[k] / (variance.sqrt() * self.pattern_norm)) as f32
+            };
+            if quality > best.quality {
+                best.quality = quality;
+                best.shift = (first + k) as isize - self.ref_start as isize;
+            }
+        }
+        best.kept = best.quality >= self.min_quality;
+        best
+    }
+
+    /// Shifts the trace so that the pattern lies at its reference position.
+    pub fn align(&self, trace: ArrayView1<f32>) -> (Array1<f32>, AlignReport) {
+        let report = self.find_shift(trace);
+        let last = trace.len() as isize - 1;
+        let aligned = Array1::from_shape_fn(trace.len(), |i| {
+            trace[(i as isize + report.shift).clamp(0, last) as usize]
+        });
+        (aligned, report)
+    }
+
+    /// Aligns a batch of traces in parallel and drops the traces (and their
+    /// metadata rows) below the quality threshold. The reports cover every
+    /// input trace, rejected ones included.
+    pub fn align_batch<U: Clone>(
+        &self,
+        traces: ArrayView2<f32>,
+        metadata: ArrayView2<U>,
+    ) -> (Array2<f32>, Array2<U>, Vec<AlignReport>) {
+        let aligned: Vec<(Array1<f32>, AlignReport)> = (0..traces.shape()[0])
+            .into_par_iter()
+            .map(|i| self.align(traces.row(i)))
+            .collect();
+        let kept: Vec<usize> = (0..aligned.len()).filter(|i| aligned[*i].1.kept).collect();
+        let mut out_traces = Array2::zeros((kept.len(), traces.shape()[1]));
+        for (row, i) in kept.iter().enumerate() {
+            out_traces.row_mut(row).assign(&aligned[*i].0);
+        }
+        let reports = aligned.iter().map(|(_, report)| *report).collect();
+        (out_traces, metadata.select(Axis(0), &kept), reports)
+    }
+}
+
+impl Stage for StaticAlign {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        let (traces, metadata, _) = self.align_batch(traces.view(), metadata.view());
+        (traces, metadata)
+    }
+}
+
+/* Elastic alignment: every trace is warped onto a reference trace along the
+dynamic time warping (DTW) path minimising the sum of absolute differences.
+The path is computed with FastDTW: it is found on traces downsampled by two,
+projected back and refined within `radius` samples around the projection,
+recursively, which is linear in the trace length instead of quadratic.
+Alternatively a Sakoe-Chiba band restricts the full DTW to `band` samples
+around the diagonal. Each reference sample receives the mean of the trace
+samples matched with it, so the warped traces keep the reference length. */
+
+pub struct ElasticAlign {
+    reference: Vec<f64>,
+    radius: usize,
+    band: Option<usize>,
+}
+
+/// Range of trace indices allowed for each reference index.
+type Window = Vec<(usize, usize)>;
+
+impl ElasticAlign {
+    pub fn new(reference: ArrayView1<f32>, radius: usize) -> Self {
+        Self {
+            reference: reference.iter().map(|x| *x as f64).collect(),
+            radius,
+            band: None,
+        }
+    }
+
+    /// Uses a full DTW constrained to `band` samples around the diagonal
+    /// instead of FastDTW.
+    pub fn band(&mut self, band: usize) {
+        self.band = Some(band);
+    }
+
+    /// Warping path as (reference index, trace index) pairs and its cost.
+    pub fn path(&self, trace: ArrayView1<f32>) -> (Vec<(usize, usize)>, f64) {
+        let y: Vec<f64> = trace.iter().map(|x| *x as f64).collect();
+        match self.band {
+            Some(band) => {
+                let (n, m) = (self.reference.len(), y.len());
+                let window = (0..n)
+                    .map(|i| {
+                        let center = i * (m - 1) / (n - 1).max(1);
+                        (center.saturating_sub(band), (center + band).min(m - 1))
+                    })
+                    .collect();
+                dtw(&self.reference, &y, &window)
+            }
+            None => fast_dtw(&self.reference, &y, self.radius),
+        }
+    }
+
+    pub fn warp(&self, trace: ArrayView1<f32>) -> Array1<f32> {
+        let (path, _) = self.path(trace);
+        let mut sum = vec![0.0; self.reference.len()];
+        let mut count = vec![0usize; self.reference.len()];
+        for (i, j) in path {
+            sum[i] += trace[j];
+            count[i] += 1;
+        }
+        Array1::from_shape_fn(sum.len(), |i| sum[i] / count[i] as f32)
+    }
+
+    /// Warps a batch of traces in parallel, the metadata rows are unchanged.
+    pub fn align_batch(&self, traces: ArrayView2<f32>) -> Array2<f32> {
+        let warped: Vec<Array1<f32>> = (0..traces.shape()[0])
+            .into_par_iter()
+            .map(|i| self.warp(traces.row(i)))
+            .collect();
+        let mut out_traces = Array2::zeros((warped.len(), self.reference.len()));
+        for (i, trace) in warped.iter().enumerate() {
+            out_traces.row_mut(i).assign(trace);
+        }
+        out_traces
+    }
+}
+
+impl Stage for ElasticAlign {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        (self.align_batch(traces.view()), metadata)
+    }
+
+    fn len_samples(&self, _len_in: usize) -> usize {
+        self.reference.len()
+    }
+}
+
+fn coarsen(x: &[f64]) -> Vec<f64> {
+    x.chunks(2)
+        .map(|pair| pair.iter().sum::<f64>() / pair.len() as f64)
+        .collect()
+}
+
+fn fast_dtw(x: &[f64], y: &[f64], radius: usize) -> (Vec<(usize, usize)>, f64) {
+    let min_size = radius + 2;
+    if x.len() <= min_size || y.len() <= min_size {
+        let window = vec![(0, y.len() - 1); x.len()];
+        return dtw(x, y, &window);
+    }
+    let (coarse_path, _) = fast_dtw(&coarsen(x), &coarsen(y), radius);
+    /* Every coarse cell covers 2 x 2 cells, widened by the radius */
+    let mut window: Window = vec![(usize::MAX, 0); x.len()];
+    for (i, j) in coarse_path {
+        let rows = (2 * i).saturating_sub(radius)..(2 * i + 2 + radius).min(x.len());
+        let low = (2 * j).saturating_sub(radius);
+        let high = (2 * j + 1 + radius).min(y.len() - 1);
+        for row in rows {
+            window[row].0 = window[row].0.min(low);
+            window[row].1 = window[row].1.max(high);
+        }
+    }
+    dtw(x, y, &window)
+}
+
+fn dtw(x: &[f64], y: &[f64], window: &Window) -> (Vec<(usize, usize)>, f64) {
+    /* Cumulative cost of the cells in the window, row by row */
+    let mut cost: Vec<Vec<f64>> = Vec::with_capacity(x.len());
+    let at = |cost: &Vec<Vec<f64>>, i: usize, j: usize| -> f64 {
+        let (low, high) = window[i];
+        if j < low || j > high {
+            f64::INFINITY
+        } else {
+            cost[i][j - low]
+        }
+    };
+    for i in 0..x.len() {
+        let (low, high) = window[i];
+        let mut row = vec![f64::INFINITY; high + 1 - low];
+        for j in low..=high {
+            let best = if i == 0 && j == 0 {
+                0.0
+            } else {
+                let mut best = f64::INFINITY;
+                if i > 0 {
+                    best = best.min(at(&cost, i - 1, j));
+                    if j > 0 {
+                        best = best.min(at(&cost, i - 1, j - 1));
+                    }
+                }
+                if j > low {
+                    best = best.min(row[j - 1 - low]);
+                }
+                best
+            };
+            row[j - low] = best + (x[i] - y[j]).abs();
+        }
+        cost.push(row);
+    }
+
+    let (mut i, mut j) = (x.len() - 1, y.len() - 1);
+    let total = at(&cost, i, j);
+    let mut path = vec![(i, j)];
+    while i > 0 || j > 0 {
+        let mut candidates = Vec::with_capacity(3);
+        if i > 0 && j > 0 {
+            candidates.push((i - 1, j - 1));
+        }
+        if i > 0 {
+            candidates.push((i - 1, j));
+        }
+        if j > 0 {
+            candidates.push((i, j - 1));
+        }
+        (i, j) = *candidates
+            .iter()
+            .min_by(|a, b| at(&cost, a.0, a.1).total_cmp(&at(&cost, b.0, b.1)))
+            .unwrap();
+        path.push((i, j));
+    }
+    path.reverse();
+    (path, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn flat_windows() {
+        let reference = array![0.0, 0.0, 1.0, 3.0, 2.0, 0.0, 0.0, 0.0];
+        let align = StaticAlign::new(reference.view(), 2, 3, 2);
+        let trace = array![5.0, 5.0, 5.0, 5.0, 6.0, 8.0, 7.0, 5.0];
+        let report = align.find_shift(trace.view());
+        assert_eq!(report.shift, 2);
+        assert!((report.quality - 1.0).abs() < 1e-6);
+        let report = align.find_shift(Array1::from_elem(8, 1e7).view());
+        assert_eq!(report.quality, 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "is constant")]
+    fn constant_reference() {
+        StaticAlign::new(Array1::from_elem(8, 1.0).view(), 2, 3, 2);
+    }
+}
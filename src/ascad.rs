@@ -0,0 +1,433 @@
+use crate::dataset::{MetadataSource, TraceSource};
+use hdf5_metno_sys::h5::{herr_t, H5open};
+use hdf5_metno_sys::h5d::{H5Dclose, H5Dget_space, H5Dget_type, H5Dopen2, H5Dread};
+use hdf5_metno_sys::h5f::{H5Fclose, H5Fopen, H5F_ACC_RDONLY};
+use hdf5_metno_sys::h5i::hid_t;
+use hdf5_metno_sys::h5p::H5P_DEFAULT;
+use hdf5_metno_sys::h5s::{
+    H5Sclose, H5Screate_simple, H5Sget_simple_extent_dims, H5Sget_simple_extent_ndims,
+    H5Sselect_hyperslab, H5S_SELECT_SET,
+};
+use hdf5_metno_sys::h5t::{
+    H5T_class_t, H5Tarray_create2, H5Tclose, H5Tcreate, H5Tget_array_dims2, H5Tget_array_ndims,
+    H5Tget_member_index, H5Tget_member_type, H5Tinsert, H5T_NATIVE_FLOAT, H5T_NATIVE_UCHAR,
+};
+use ndarray::{concatenate, Array1, Array2, Axis};
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Result};
+use std::marker::PhantomData;
+use std::ptr;
+
+/* ASCAD datasets (HDF5, enabled with the hdf5 feature). The file holds a
+Profiling_traces and an Attack_traces group, each with a traces dataset
+(one trace per row, converted to f32 when read) and a metadata dataset of a
+compound type whose fields plaintext, key, ciphertext and masks are byte
+arrays. Batches are read with hyperslab selections, so only the requested
+rows and samples are loaded. Every identifier and status returned by the
+HDF5 library is checked, the identifiers being closed when dropped. */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Split {
+    Profiling,
+    Attack,
+}
+
+impl Split {
+    fn group(&self) -> &'static str {
+        match self {
+            Split::Profiling => "Profiling_traces",
+            Split::Attack => "Attack_traces",
+        }
+    }
+}
+
+/// A batch of traces with their ASCAD metadata, empty arrays standing for
+/// the fields missing from the file.
+pub struct AscadBatch {
+    pub traces: Array2<f32>,
+    pub plaintext: Array2<u8>,
+    pub key: Array2<u8>,
+    pub ciphertext: Array2<u8>,
+    pub masks: Array2<u8>,
+}
+
+pub struct AscadFile {
+    file: Handle,
+}
+
+impl AscadFile {
+    pub fn open(path: &str) -> Result<Self> {
+        let name = c_string(path)?;
+        check(unsafe { H5open() }, "library initialisation")?;
+        let file = unsafe { H5Fopen(name.as_ptr(), H5F_ACC_RDONLY, H5P_DEFAULT) };
+        Ok(Self {
+            file: Handle::new(file, H5Fclose, path)?,
+        })
+    }
+
+    pub fn split(&self, split: Split) -> Result<AscadSplit<'_>> {
+        let traces = open_dataset(&self.file, &format!("{}/traces", split.group()))?;
+        let metadata = open_dataset(&self.file, &format!("{}/metadata", split.group()))?;
+        let space = Handle::new(unsafe { H5Dget_space(traces.id) }, H5Sclose, "traces")?;
+        let rank = unsafe { H5Sget_simple_extent_ndims(space.id) };
+        if rank != 2 {
+            return Err(invalid("traces is not a 2-D dataset"));
+        }
+        let mut dims = [0; 2];
+        let rank =
+            unsafe { H5Sget_simple_extent_dims(space.id, dims.as_mut_ptr(), ptr::null_mut()) };
+        check(rank, "traces")?;
+        Ok(AscadSplit {
+            traces,
+            metadata,
+            len_traces: dims[0] as usize,
+            width: dims[1] as usize,
+            window: 0..dims[1] as usize,
+            file: PhantomData,
+        })
+    }
+}
+
+pub struct AscadSplit<'a> {
+    traces: Handle,
+    metadata: Handle,
+    len_traces: usize,
+    width: usize,
+    window: std::ops::Range<usize>,
+    file: PhantomData<&'a AscadFile>,
+}
+
+impl AscadSplit<'_> {
+    /// Restricts the traces to the samples start..end.
+    pub fn window(&mut self, start: usize, end: usize) {
+        assert!(start <= end && end <= self.width);
+        self.window = start..end;
+    }
+
+    pub fn len_traces(&self) -> usize {
+        self.len_traces
+    }
+
+    pub fn len_samples(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Traces start..start + len (clipped to the split) and their metadata.
+    pub fn batch(&self, start: usize, len: usize) -> Result<AscadBatch> {
+        let end = (start + len).min(self.len_traces);
+        let start = start.min(end);
+        Ok(AscadBatch {
+            traces: self.read_traces(start, end)?,
+            plaintext: self.field(start, end, "plaintext")?,
+            key: self.field(start, end, "key")?,
+            ciphertext: self.field(start, end, "ciphertext")?,
+            masks: self.field(start, end, "masks")?,
+        })
+    }
+
+    /// Batches of `len` traces in order.
+    pub fn batches(&self, len: usize) -> impl Iterator<Item = Result<AscadBatch>> + '_ {
+        (0..self.len_traces)
+            .step_by(len)
+            .map(move |start| self.batch(start, len))
+    }
+
+    /// The key of the split when every trace used the same key (attack sets).
+    pub fn known_key(&self) -> Result<Option<Array1<u8>>> {
+        let keys = self.field(0, self.len_traces, "key")?;
+        let Some(first) = keys.axis_iter(Axis(0)).next() else {
+            return Ok(None);
+        };
+        if keys.axis_iter(Axis(0)).all(|k| k == first) {
+            Ok(Some(first.to_owned()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn read_traces(&self, start: usize, end: usize) -> Result<Array2<f32>> {
+        let rows = end - start;
+        let mut traces = Array2::<f32>::zeros((rows, self.len_samples()));
+        if rows == 0 || self.len_samples() == 0 {
+            return Ok(traces);
+        }
+        let offset = [start as u64, self.window.start as u64];
+        let count = [rows as u64, self.len_samples() as u64];
+        let (file_space, mem_space) = select(&self.traces, &offset, &count, "traces")?;
+        let status = unsafe {
+            H5Dread(
+                self.traces.id,
+                *H5T_NATIVE_FLOAT,
+                mem_space.id,
+                file_space.id,
+                H5P_DEFAULT,
+                traces.as_mut_ptr().cast(),
+            )
+        };
+        check(status, "traces")?;
+        Ok(traces)
+    }
+
+    /// Byte array field `name` of the metadata rows start..end, with zero
+    /// columns when the field does not exist.
+    fn field(&self, start: usize, end: usize, name: &str) -> Result<Array2<u8>> {
+        let c_name = c_string(name)?;
+        let rows = end - start;
+        let file_type = Handle::new(unsafe { H5Dget_type(self.metadata.id) }, H5Tclose, name)?;
+        let index = unsafe { H5Tget_member_index(file_type.id, c_name.as_ptr()) };
+        if index < 0 {
+            return Ok(Array2::zeros((rows, 0)));
+        }
+        let member = unsafe { H5Tget_member_type(file_type.id, index as u32) };
+        let member = Handle::new(member, H5Tclose, name)?;
+        if unsafe { H5Tget_array_ndims(member.id) } != 1 {
+            return Err(invalid(&format!(
+                "metadata field {name} is not a byte array"
+            )));
+        }
+        let mut len = [0u64; 1];
+        check(
+            unsafe { H5Tget_array_dims2(member.id, len.as_mut_ptr()) },
+            name,
+        )?;
+        let mut values = Array2::<u8>::zeros((rows, len[0] as usize));
+        if rows == 0 {
+            return Ok(values);
+        }
+        /* Compound type holding only the requested field */
+        let array = unsafe { H5Tarray_create2(*H5T_NATIVE_UCHAR, 1, len.as_ptr()) };
+        let array = Handle::new(array, H5Tclose, name)?;
+        let mem_type = unsafe { H5Tcreate(H5T_class_t::H5T_COMPOUND, len[0] as usize) };
+        let mem_type = Handle::new(mem_type, H5Tclose, name)?;
+        check(
+            unsafe { H5Tinsert(mem_type.id, c_name.as_ptr(), 0, array.id) },
+            name,
+        )?;
+        let (file_space, mem_space) =
+            select(&self.metadata, &[start as u64], &[rows as u64], name)?;
+        let status = unsafe {
+            H5Dread(
+                self.metadata.id,
+                mem_type.id,
+                mem_space.id,
+                file_space.id,
+                H5P_DEFAULT,
+                values.as_mut_ptr().cast(),
+            )
+        };
+        check(status, name)?;
+        Ok(values)
+    }
+}
+
+impl TraceSource for AscadSplit<'_> {
+    fn len_traces(&self) -> usize {
+        self.len_traces
+    }
+
+    fn len_samples(&self) -> usize {
+        self.window.len()
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn window(&mut self, start: usize, end: usize) {
+        AscadSplit::window(self, start, end);
+    }
+
+    /// Panics when the file cannot be read.
+    fn batch(&self, start: usize, len: usize) -> Array2<f32> {
+        let end = (start + len).min(self.len_traces);
+        self.read_traces(start.min(end), end)
+            .expect("cannot read the ASCAD traces")
+    }
+}
+
+/// Rows made of the plaintext, key, ciphertext and masks fields, in that
+/// order, the fields missing from the file taking no column.
+impl MetadataSource for AscadSplit<'_> {
+    fn len_traces(&self) -> usize {
+        self.len_traces
+    }
+
+    fn metadata(&self, start: usize, len: usize) -> Result<Array2<u8>> {
+        let end = (start + len).min(self.len_traces);
+        let start = start.min(end);
+        let fields = ["plaintext", "key", "ciphertext", "masks"]
+            .iter()
+            .map(|name| self.field(start, end, name))
+            .collect::<Result<Vec<Array2<u8>>>>()?;
+        let views: Vec<_> = fields.iter().map(|f| f.view()).collect();
+        Ok(concatenate(Axis(1), &views).unwrap())
+    }
+}
+
+/// Identifier of an HDF5 object, closed when dropped.
+struct Handle {
+    id: hid_t,
+    close: unsafe extern "C" fn(hid_t) -> herr_t,
+}
+
+impl Handle {
+    /// Takes the identifier returned by an HDF5 call, negative on failure.
+    fn new(id: hid_t, close: unsafe extern "C" fn(hid_t) -> herr_t, what: &str) -> Result<Self> {
+        check(id, what)?;
+        Ok(Self { id, close })
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { (self.close)(self.id) };
+    }
+}
+
+fn open_dataset(file: &Handle, path: &str) -> Result<Handle> {
+    let name = c_string(path)?;
+    let dataset = unsafe { H5Dopen2(file.id, name.as_ptr(), H5P_DEFAULT) };
+    Handle::new(dataset, H5Dclose, path)
+}
+
+/// Selects the block `offset`, `count` of `dataset`, returning the file and
+/// memory dataspaces to read it.
+fn select(dataset: &Handle, offset: &[u64], count: &[u64], what: &str) -> Result<(Handle, Handle)> {
+    let file_space = Handle::new(unsafe { H5Dget_space(dataset.id) }, H5Sclose, what)?;
+    let status = unsafe {
+        H5Sselect_hyperslab(
+            file_space.id,
+            H5S_SELECT_SET,
+            offset.as_ptr(),
+            ptr::null(),
+            count.as_ptr(),
+            ptr::null(),
+        )
+    };
+    check(status, what)?;
+    let mem_space = unsafe { H5Screate_simple(count.len() as i32, count.as_ptr(), ptr::null()) };
+    let mem_space = Handle::new(mem_space, H5Sclose, what)?;
+    Ok((file_space, mem_space))
+}
+
+fn c_string(value: &str) -> Result<CString> {
+    CString::new(value).map_err(|_| invalid("name contains a nul byte"))
+}
+
+/// HDF5 calls return a negative value on failure.
+fn check(status: impl Into<i64>, what: &str) -> Result<()> {
+    if status.into() < 0 {
+        Err(Error::other(format!("HDF5 error on {what}")))
+    } else {
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hdf5_metno_sys::h5d::{H5Dcreate2, H5Dwrite};
+    use hdf5_metno_sys::h5f::{H5Fcreate, H5F_ACC_TRUNC};
+    use hdf5_metno_sys::h5g::{H5Gclose, H5Gcreate2};
+    use hdf5_metno_sys::h5t::H5T_NATIVE_SCHAR;
+    use ndarray::array;
+
+    /// Writes a split of 3 traces of 4 int8 samples, with 2-byte plaintexts
+    /// and keys, like the ASCAD files.
+    fn write_file(path: &str) {
+        let traces: Array2<i8> = array![[1, -2, 3, -4], [5, -6, 7, -8], [9, -10, 11, -12]];
+        /* Rows of the compound type: plaintext then key */
+        let metadata: [u8; 12] = [0, 1, 7, 7, 2, 3, 7, 7, 4, 5, 7, 7];
+        let name = |n: &str| CString::new(n).unwrap();
+        unsafe {
+            H5open();
+            let file = H5Fcreate(name(path).as_ptr(), H5F_ACC_TRUNC, H5P_DEFAULT, H5P_DEFAULT);
+            let group = H5Gcreate2(
+                file,
+                name("Attack_traces").as_ptr(),
+                H5P_DEFAULT,
+                H5P_DEFAULT,
+                H5P_DEFAULT,
+            );
+
+            let dims = [3u64, 4];
+            let space = H5Screate_simple(2, dims.as_ptr(), ptr::null());
+            let dataset = H5Dcreate2(
+                group,
+                name("traces").as_ptr(),
+                *H5T_NATIVE_SCHAR,
+                space,
+                H5P_DEFAULT,
+                H5P_DEFAULT,
+                H5P_DEFAULT,
+            );
+            H5Dwrite(
+                dataset,
+                *H5T_NATIVE_SCHAR,
+                space,
+                space,
+                H5P_DEFAULT,
+                traces.as_ptr().cast(),
+            );
+            H5Dclose(dataset);
+            H5Sclose(space);
+
+            let len = [2u64];
+            let array = H5Tarray_create2(*H5T_NATIVE_UCHAR, 1, len.as_ptr());
+            let row = H5Tcreate(H5T_class_t::H5T_COMPOUND, 4);
+            H5Tinsert(row, name("plaintext").as_ptr(), 0, array);
+            H5Tinsert(row, name("key").as_ptr(), 2, array);
+            let rows = [3u64];
+            let space = H5Screate_simple(1, rows.as_ptr(), ptr::null());
+            let dataset = H5Dcreate2(
+                group,
+                name("metadata").as_ptr(),
+                row,
+                space,
+                H5P_DEFAULT,
+                H5P_DEFAULT,
+                H5P_DEFAULT,
+            );
+            H5Dwrite(
+                dataset,
+                row,
+                space,
+                space,
+                H5P_DEFAULT,
+                metadata.as_ptr().cast(),
+            );
+            H5Dclose(dataset);
+            H5Sclose(space);
+            H5Tclose(row);
+            H5Tclose(array);
+            H5Gclose(group);
+            H5Fclose(file);
+        }
+    }
+
+    #[test]
+    fn read_split() {
+        let path = std::env::temp_dir().join("cpa_ascad.h5");
+        let path = path.to_str().unwrap();
+        write_file(path);
+        let file = AscadFile::open(path).unwrap();
+        assert!(file.split(Split::Profiling).is_err());
+        let mut split = file.split(Split::Attack).unwrap();
+        assert_eq!(split.len_traces(), 3);
+        assert_eq!(split.len_samples(), 4);
+        split.window(1, 3);
+        let batch = split.batch(1, 10).unwrap();
+        assert_eq!(batch.traces, array![[-6.0f32, 7.0], [-10.0, 11.0]]);
+        assert_eq!(batch.plaintext, array![[2u8, 3], [4, 5]]);
+        assert_eq!(batch.key, array![[7u8, 7], [7, 7]]);
+        assert_eq!(batch.ciphertext.shape(), &[2, 0]);
+        assert_eq!(split.known_key().unwrap(), Some(array![7u8, 7]));
+        drop(split);
+        drop(file);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+use crate::dataset::{Dataset, MetadataSource, TraceSource};
+use crate::npy::NpyElement;
+use ndarray::{concatenate, Array1, Array2, Axis};
+use num_traits::AsPrimitive;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/* ChipWhisperer projects: a project file name.cwp next to a name_data
+directory. The [Trace Management] section of the project lists the trace
+segments (tracefileN = traces/config_<prefix>.cfg, enabledN = True), each
+segment config gives its prefix in [Trace Config], and the segment arrays are
+stored as traces/<prefix>traces.npy, textin.npy, textout.npy and
+keylist.npy. Projects without a segment list are read from every
+*traces.npy file of the traces directory. */
+
+/// A batch of traces with the metadata recorded by ChipWhisperer.
+pub struct CwBatch<T> {
+    pub traces: Array2<T>,
+    pub plaintext: Array2<u8>,
+    pub ciphertext: Array2<u8>,
+    pub key: Array2<u8>,
+}
+
+pub struct CwProject<T: NpyElement = f64> {
+    /// Traces and plaintexts.
+    inputs: Dataset<T, u8>,
+    /// Ciphertexts and keys.
+    outputs: Dataset<u8, u8>,
+    prefixes: Vec<String>,
+}
+
+impl<T: NpyElement> CwProject<T> {
+    /// Opens the project from its .cwp file.
+    pub fn open(path: &str) -> Result<Self> {
+        let cwp = Path::new(path);
+        let data = cwp.with_file_name(format!(
+            "{}_data",
+            cwp.file_stem().and_then(|s| s.to_str()).unwrap_or_default()
+        ));
+        let config = parse_ini(&fs::read_to_string(cwp)?);
+        let mut prefixes = Vec::new();
+        for i in 0.. {
+            let Some(segment) =
+                config.get(&("Trace Management".to_string(), format!("tracefile{i}")))
+            else {
+                break;
+            };
+            let enabled = config
+                .get(&("Trace Management".to_string(), format!("enabled{i}")))
+                .is_none_or(|e| e.eq_ignore_ascii_case("true"));
+            if !enabled {
+                continue;
+            }
+            let segment_path = data.join(segment);
+            let segment_config = parse_ini(&fs::read_to_string(&segment_path)?);
+            let prefix = segment_config
+                .get(&("Trace Config".to_string(), "prefix".to_string()))
+                .ok_or_else(|| invalid(format!("no prefix in {}", segment_path.display())))?;
+            let dir = segment_path.parent().unwrap_or(&data);
+            prefixes.push(dir.join(prefix));
+        }
+        if prefixes.is_empty() {
+            prefixes = discover_prefixes(&data.join("traces"))?;
+        }
+        Self::from_prefixes(&prefixes)
+    }
+
+    /// Opens the segments whose files are <prefix>traces.npy, ...
+    pub fn from_prefixes(prefixes: &[PathBuf]) -> Result<Self> {
+        let file = |prefix: &PathBuf, name: &str| format!("{}{name}.npy", prefix.display());
+        let inputs: Vec<(String, String)> = prefixes
+            .iter()
+            .map(|p| (file(p, "traces"), file(p, "textin")))
+            .collect();
+        let outputs: Vec<(String, String)> = prefixes
+            .iter()
+            .map(|p| (file(p, "textout"), file(p, "keylist")))
+            .collect();
+        let inputs = Dataset::open(&inputs)?;
+        let outputs = Dataset::open(&outputs)?;
+        if inputs.len_traces() != outputs.len_traces() {
+            return Err(invalid(
+                "textout or keylist does not match the traces".into(),
+            ));
+        }
+        Ok(Self {
+            inputs,
+            outputs,
+            prefixes: prefixes.iter().map(|p| p.display().to_string()).collect(),
+        })
+    }
+
+    /// Restricts the traces to the samples start..end.
+    pub fn window(&mut self, start: usize, end: usize) {
+        self.inputs.window(start, end);
+    }
+
+    pub fn len_traces(&self) -> usize {
+        self.inputs.len_traces()
+    }
+
+    pub fn len_samples(&self) -> usize {
+        self.inputs.len_samples()
+    }
+
+    /// File prefixes of the segments, in order.
+    pub fn segments(&self) -> &[String] {
+        &self.prefixes
+    }
+
+    pub fn batch(&self, start: usize, len: usize) -> CwBatch<T> {
+        let (traces, plaintext) = self.inputs.batch(start, len);
+        let (ciphertext, key) = self.outputs.batch(start, len);
+        CwBatch {
+            traces,
+            plaintext,
+            ciphertext,
+            key,
+        }
+    }
+
+    /// Batches of `len` traces in order.
+    pub fn batches(&self, len: usize) -> impl Iterator<Item = CwBatch<T>> + '_ {
+        (0..self.len_traces())
+            .step_by(len)
+            .map(move |start| self.batch(start, len))
+    }
+
+    /// The key of the campaign when every trace used the same key.
+    pub fn known_key(&self) -> Option<Array1<u8>> {
+        let mut key: Option<Array1<u8>> = None;
+        for (_, keys) in self.outputs.batches(10_000) {
+            for row in keys.axis_iter(Axis(0)) {
+                match &key {
+                    None => key = Some(row.to_owned()),
+                    Some(k) if *k != row => return None,
+                    _ => {}
+                }
+            }
+        }
+        key
+    }
+}
+
+impl<T: NpyElement + AsPrimitive<f32>> TraceSource for CwProject<T> {
+    fn len_traces(&self) -> usize {
+        CwProject::len_traces(self)
+    }
+
+    fn len_samples(&self) -> usize {
+        CwProject::len_samples(self)
+    }
+
+    fn width(&self) -> usize {
+        self.inputs.width()
+    }
+
+    fn window(&mut self, start: usize, end: usize) {
+        CwProject::window(self, start, end);
+    }
+
+    fn batch(&self, start: usize, len: usize) -> Array2<f32> {
+        self.inputs.batch(start, len).0.mapv(|t| t.as_())
+    }
+}
+
+/// Rows made of the plaintext, ciphertext and key, in that order.
+impl<T: NpyElement> MetadataSource for CwProject<T> {
+    fn len_traces(&self) -> usize {
+        CwProject::len_traces(self)
+    }
+
+    fn metadata(&self, start: usize, len: usize) -> Result<Array2<u8>> {
+        let (_, plaintext) = self.inputs.batch(start, len);
+        let (ciphertext, key) = self.outputs.batch(start, len);
+        Ok(concatenate(Axis(1), &[plaintext.view(), ciphertext.view(), key.view()]).unwrap())
+    }
+}
+
+/// Prefixes of the *traces.npy files of `dir`, sorted.
+fn discover_prefixes(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut prefixes: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            let prefix = name.to_str()?.strip_suffix("traces.npy")?;
+            Some(dir.join(prefix))
+        })
+        .collect();
+    prefixes.sort();
+    if prefixes.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no trace segment in {}", dir.display()),
+        ));
+    }
+    Ok(prefixes)
+}
+
+/// (section, key) -> value pairs of an INI file.
+fn parse_ini(text: &str) -> HashMap<(String, String), String> {
+    let mut values = HashMap::new();
+    let mut section = String::new();
+    for line in text.lines().map(|l| l.trim()) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+        } else if let Some((key, value)) = line.split_once('=') {
+            values.insert(
+                (section.clone(), key.trim().to_string()),
+                value.trim().to_string(),
+            );
+        }
+    }
+    values
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
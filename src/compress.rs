@@ -0,0 +1,151 @@
+use crate::pipeline::{map_rows, Stage};
+use ndarray::{s, Array1, Array2, ArrayView1};
+
+/* Trace compression: consecutive windows of samples are reduced to a single
+sample before the engines allocate their guess_range x len_samples arrays.
+The windows either have a fixed length or follow the clock of the target,
+in which case the (possibly fractional) clock period in samples and the
+offset of the first cycle are given and every output sample integrates one
+clock cycle. Samples after the last complete window are dropped. */
+
+#[derive(Clone, Copy, Debug)]
+pub enum Reduction {
+    Sum,
+    Mean,
+    Max,
+    AbsSum,
+}
+
+impl Reduction {
+    pub(crate) fn reduce(&self, window: ArrayView1<f32>) -> f32 {
+        match self {
+            Reduction::Sum => window.iter().map(|x| *x as f64).sum::<f64>() as f32,
+            Reduction::Mean => {
+                (window.iter().map(|x| *x as f64).sum::<f64>() / window.len() as f64) as f32
+            }
+            Reduction::Max => window.iter().fold(f32::NEG_INFINITY, |m, x| m.max(*x)),
+            Reduction::AbsSum => window.iter().map(|x| x.abs() as f64).sum::<f64>() as f32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Integrate {
+    period: f64,
+    offset: usize,
+    reduction: Reduction,
+}
+
+impl Integrate {
+    /// Fixed windows of `len_window` samples starting at the first sample.
+    pub fn new(len_window: usize, reduction: Reduction) -> Self {
+        assert!(len_window > 0, "the integration window must not be empty");
+        Self {
+            period: len_window as f64,
+            offset: 0,
+            reduction,
+        }
+    }
+
+    /// One window per clock cycle of `period` samples, the first cycle
+    /// starting at sample `offset`.
+    pub fn clock(period: f64, offset: usize, reduction: Reduction) -> Self {
+        assert!(
+            period > 0.0 && period.is_finite(),
+            "invalid clock period {period}"
+        );
+        Self {
+            period,
+            offset,
+            reduction,
+        }
+    }
+
+    /// First sample of the input trace covered by the output sample `index`.
+    pub fn window_start(&self, index: usize) -> usize {
+        self.offset + (index as f64 * self.period).round() as usize
+    }
+
+    pub fn len_windows(&self, len_in: usize) -> usize {
+        let mut len = (len_in.saturating_sub(self.offset) as f64 / self.period) as usize;
+        /* Rounding of the window bounds may push the last one past the trace */
+        while len > 0 && self.window_start(len) > len_in {
+            len -= 1;
+        }
+        len
+    }
+
+    pub fn apply(&self, trace: ArrayView1<f32>) -> Array1<f32> {
+        Array1::from_shape_fn(self.len_windows(trace.len()), |i| {
+            let start = self.window_start(i);
+            let end = self.window_start(i + 1);
+            self.reduction
+                .reduce(trace.slice(s![start..end.max(start + 1)]))
+        })
+    }
+}
+
+impl Stage for Integrate {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        let len_out = self.len_samples(traces.shape()[1]);
+        (
+            map_rows(traces.view(), len_out, |t| self.apply(t)),
+            metadata,
+        )
+    }
+
+    fn len_samples(&self, len_in: usize) -> usize {
+        self.len_windows(len_in)
+    }
+}
+
+/// Keeps one sample every `factor` samples, starting at sample `offset`.
+/// Apply a low-pass filter first to avoid aliasing.
+#[derive(Clone, Copy, Debug)]
+pub struct Decimate {
+    factor: usize,
+    offset: usize,
+}
+
+impl Decimate {
+    pub fn new(factor: usize, offset: usize) -> Self {
+        assert!(factor > 0, "the decimation factor must be at least 1");
+        Self { factor, offset }
+    }
+
+    /// Sample of the input trace kept as the output sample `index`.
+    pub fn original_index(&self, index: usize) -> usize {
+        self.offset + index * self.factor
+    }
+
+    pub fn apply(&self, trace: ArrayView1<f32>) -> Array1<f32> {
+        trace
+            .iter()
+            .skip(self.offset)
+            .step_by(self.factor)
+            .copied()
+            .collect()
+    }
+}
+
+impl Stage for Decimate {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        let len_out = self.len_samples(traces.shape()[1]);
+        (
+            map_rows(traces.view(), len_out, |t| self.apply(t)),
+            metadata,
+        )
+    }
+
+    fn len_samples(&self, len_in: usize) -> usize {
+        len_in.saturating_sub(self.offset).div_ceil(self.factor)
+    }
+}
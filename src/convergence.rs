@@ -0,0 +1,81 @@
+use crate::stats::difference_p_value;
+use ndarray::ArrayView2;
+
+/* This class decides when a campaign can stop: the best guess must stay the
+same for `stable_checkpoints` consecutive checkpoints and be separated from
+the runner-up according to the criterion */
+
+#[derive(Clone, Copy, Debug)]
+pub enum Criterion {
+    /// Minimum difference between the best and the second correlation.
+    Margin(f32),
+    /// Maximum p-value of the best correlation not exceeding the second.
+    Significance(f64),
+}
+
+#[derive(Clone, Debug)]
+pub struct ConvergenceMonitor {
+    stable_checkpoints: usize,
+    criterion: Criterion,
+    last_guess: Option<usize>,
+    stable_count: usize,
+    stopped_at: Option<usize>,
+}
+
+impl ConvergenceMonitor {
+    pub fn new(stable_checkpoints: usize, criterion: Criterion) -> Self {
+        Self {
+            stable_checkpoints,
+            criterion,
+            last_guess: None,
+            stable_count: 0,
+            stopped_at: None,
+        }
+    }
+
+    /// Feeds the `guess_range x 1` max correlation array of a checkpoint
+    /// reached after `traces_no` traces and returns true once converged.
+    pub fn check(&mut self, traces_no: usize, max_corr: ArrayView2<f32>) -> bool {
+        if self.stopped_at.is_some() {
+            return true;
+        }
+        let (mut best, mut second) = ((0, 0.0), 0.0);
+        for (guess, value) in max_corr.column(0).iter().enumerate() {
+            if *value > best.1 {
+                second = best.1;
+                best = (guess, *value);
+            } else if *value > second {
+                second = *value;
+            }
+        }
+        if self.last_guess == Some(best.0) {
+            self.stable_count += 1;
+        } else {
+            self.last_guess = Some(best.0);
+            self.stable_count = 1;
+        }
+        let separated = match self.criterion {
+            Criterion::Margin(margin) => best.1 - second >= margin,
+            Criterion::Significance(alpha) => {
+                difference_p_value(best.1 as f64, second as f64, traces_no) <= alpha
+            }
+        };
+        if self.stable_count >= self.stable_checkpoints && separated {
+            self.stopped_at = Some(traces_no);
+        }
+        self.stopped_at.is_some()
+    }
+
+    pub fn converged(&self) -> bool {
+        self.stopped_at.is_some()
+    }
+
+    /// Number of traces at the checkpoint where the campaign converged.
+    pub fn stopped_at(&self) -> Option<usize> {
+        self.stopped_at
+    }
+
+    pub fn guess(&self) -> Option<usize> {
+        self.last_guess
+    }
+}
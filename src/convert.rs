@@ -0,0 +1,332 @@
+use crate::compress::Decimate;
+use crate::dataset::{MetadataSource, TraceSource};
+use crate::npy::{Dtype, NpyWriter};
+use crate::pipeline::{Pipeline, Stage};
+use crate::trs::{SampleCoding, TrsWriter};
+use ndarray::{s, Array2, ArrayView2};
+use std::io::{Error, ErrorKind, Result};
+
+/* Conversion of trace sets between formats: the traces of any TraceSource,
+with the bytes of an optional MetadataSource, are streamed batch by batch to
+NPY files (traces and metadata) or to a TRS file. Several sources, e.g. NPY
+datasets, ChipWhisperer projects, ASCAD splits or captures, are merged into
+one output by a Concat of sources. On the way the samples can
+be cropped, a subset of the traces kept (start..end with a step) and the
+batches run through preprocessing stages such as decimation; the output
+element type or sample coding is chosen independently of the input. Only
+one batch of input traces is held in memory at a time. Integer TRS codings
+store the samples divided by the y scale of the file and rounded, the scale
+by default mapping the largest sample to the largest value of the coding:
+the traces are then read and processed twice. Integer NPY files have no
+scale and only take integer samples, such as raw ADC values. */
+
+pub struct Converter {
+    crop: Option<(usize, usize)>,
+    start: usize,
+    end: Option<usize>,
+    step: usize,
+    stages: Pipeline,
+    batch_len: usize,
+    y_scale: Option<f32>,
+}
+
+impl Default for Converter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Converter {
+    /// Copies every trace and sample, 1000 traces at a time.
+    pub fn new() -> Self {
+        Self {
+            crop: None,
+            start: 0,
+            end: None,
+            step: 1,
+            stages: Pipeline::new(),
+            batch_len: 1000,
+            y_scale: None,
+        }
+    }
+
+    /// Keeps the samples start..end of the input traces.
+    pub fn crop(&mut self, start: usize, end: usize) {
+        self.crop = Some((start, end));
+    }
+
+    /// Keeps the traces start, start + step, ... before `end` (None for the
+    /// last trace of the source).
+    pub fn traces(&mut self, start: usize, end: Option<usize>, step: usize) {
+        assert!(step > 0);
+        self.start = start;
+        self.end = end;
+        self.step = step;
+    }
+
+    /// Keeps one sample out of `factor`, after cropping.
+    pub fn decimate(&mut self, factor: usize) {
+        self.stages.add(Decimate::new(factor, 0));
+    }
+
+    /// Appends a preprocessing stage, applied after cropping in the order
+    /// the stages are added.
+    pub fn stage(&mut self, stage: impl Stage + 'static) {
+        self.stages.add(stage);
+    }
+
+    /// Number of input traces read at a time.
+    pub fn batch_len(&mut self, len: usize) {
+        self.batch_len = len.max(1);
+    }
+
+    /// Value of one unit of the TRS sample coding, instead of the scale
+    /// fitting the largest sample. Samples beyond the range of the coding
+    /// are then an error.
+    pub fn y_scale(&mut self, y_scale: f32) {
+        assert!(y_scale.is_normal(), "invalid y scale {y_scale}");
+        self.y_scale = Some(y_scale);
+    }
+
+    /// Number of samples per output trace for `source`.
+    pub fn len_samples(&self, source: &impl TraceSource) -> usize {
+        let len_in = match self.crop {
+            Some((start, end)) => end.saturating_sub(start),
+            None => source.len_samples(),
+        };
+        self.stages.len_samples(len_in)
+    }
+
+    /// Streams the selected traces of `source` and their metadata rows to
+    /// `write`, returning the number of traces written.
+    pub fn run<S: TraceSource>(
+        &mut self,
+        source: &mut S,
+        metadata: Option<&dyn MetadataSource>,
+        write: impl FnMut(ArrayView2<f32>, ArrayView2<u8>) -> Result<()>,
+    ) -> Result<usize> {
+        self.prepare(source, metadata)?;
+        self.stream(source, metadata, write)
+    }
+
+    /// Checks the metadata and the crop against `source` and crops it.
+    fn prepare<S: TraceSource>(
+        &self,
+        source: &mut S,
+        metadata: Option<&dyn MetadataSource>,
+    ) -> Result<()> {
+        if let Some(metadata) = metadata {
+            if metadata.len_traces() != source.len_traces() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "{} traces but {} metadata rows",
+                        source.len_traces(),
+                        metadata.len_traces()
+                    ),
+                ));
+            }
+        }
+        if let Some((start, end)) = self.crop {
+            if start > end || end > source.width() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "cannot crop {start}..{end} out of {} samples",
+                        source.width()
+                    ),
+                ));
+            }
+            source.window(start, end);
+        }
+        Ok(())
+    }
+
+    /// Streams the traces of a prepared source.
+    fn stream<S: TraceSource>(
+        &mut self,
+        source: &S,
+        metadata: Option<&dyn MetadataSource>,
+        mut write: impl FnMut(ArrayView2<f32>, ArrayView2<u8>) -> Result<()>,
+    ) -> Result<usize> {
+        let end = self.end.unwrap_or(usize::MAX).min(source.len_traces());
+        let mut written = 0;
+        /* Every batch is a contiguous block of input traces */
+        for first in (self.start..end).step_by(self.batch_len * self.step) {
+            let len = (self.batch_len * self.step).min(end - first);
+            let rows = s![..;self.step, ..];
+            let traces = source.batch(first, len).slice_move(rows);
+            let data = match metadata {
+                Some(m) => m.metadata(first, len)?.slice_move(rows),
+                None => Array2::zeros((traces.nrows(), 0)),
+            };
+            let (traces, data) = self.stages.process(traces, data.map(|d| *d as usize));
+            write(traces.view(), data.map(|d| *d as u8).view())?;
+            written += traces.nrows();
+        }
+        Ok(written)
+    }
+
+    /// Converts to an NPY file of `dtype` traces and, when a metadata path is
+    /// given, an NPY file of metadata bytes.
+    pub fn to_npy<S: TraceSource>(
+        &mut self,
+        source: &mut S,
+        metadata: Option<&dyn MetadataSource>,
+        traces_path: &str,
+        metadata_path: Option<&str>,
+        dtype: Dtype,
+    ) -> Result<usize> {
+        self.prepare(source, metadata)?;
+        let mut traces_writer = NpyWriter::create(traces_path, self.len_samples(source), dtype)?;
+        let mut metadata_writer = match (metadata, metadata_path) {
+            (Some(m), Some(path)) => {
+                let width = m.metadata(0, 1)?.ncols();
+                Some(NpyWriter::create(path, width, Dtype::U8)?)
+            }
+            _ => None,
+        };
+        let written = self.stream(source, metadata, |traces, data| {
+            traces_writer.write_batch(traces)?;
+            if let Some(writer) = &mut metadata_writer {
+                writer.write_bytes(data)?;
+            }
+            Ok(())
+        })?;
+        traces_writer.finish()?;
+        if let Some(writer) = metadata_writer {
+            writer.finish()?;
+        }
+        Ok(written)
+    }
+
+    /// Converts to a TRS file, the metadata bytes becoming the data of every
+    /// trace.
+    pub fn to_trs<S: TraceSource>(
+        &mut self,
+        source: &mut S,
+        metadata: Option<&dyn MetadataSource>,
+        path: &str,
+        coding: SampleCoding,
+    ) -> Result<usize> {
+        self.prepare(source, metadata)?;
+        let data_len = match metadata {
+            Some(m) => m.metadata(0, 1)?.ncols(),
+            None => 0,
+        };
+        let y_scale = match (self.y_scale, coding.max_value()) {
+            (Some(y_scale), _) => y_scale,
+            (None, None) => 1.0,
+            (None, Some(max_value)) => {
+                let mut max = 0f32;
+                self.stream(source, metadata, |traces, _| {
+                    max = traces.iter().fold(max, |m, t| m.max(t.abs()));
+                    Ok(())
+                })?;
+                if max > 0.0 {
+                    max / max_value
+                } else {
+                    1.0
+                }
+            }
+        };
+        let mut writer = TrsWriter::create(path, self.len_samples(source), data_len, coding)?;
+        writer.scale(1.0, y_scale);
+        writer.round(true);
+        let written = self.stream(source, metadata, |traces, data| {
+            writer.write_batch(traces, data)
+        })?;
+        writer.finish()?;
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::Concat;
+    use crate::npy::{NpyReader, NpySource};
+    use ndarray::array;
+
+    /// Writes `traces` as f32 and `metadata` as bytes to NPY files.
+    fn npy_files(name: &str, traces: Array2<f32>, metadata: Array2<u8>) -> (String, String) {
+        let path = |kind: &str| {
+            let path = std::env::temp_dir().join(format!("cpa_convert_{name}_{kind}.npy"));
+            path.to_string_lossy().into_owned()
+        };
+        let (traces_path, metadata_path) = (path("l"), path("p"));
+        let mut writer = NpyWriter::create(&traces_path, traces.ncols(), Dtype::F32).unwrap();
+        writer.write_batch(traces.view()).unwrap();
+        writer.finish().unwrap();
+        let mut writer = NpyWriter::create(&metadata_path, metadata.ncols(), Dtype::U8).unwrap();
+        writer.write_bytes(metadata.view()).unwrap();
+        writer.finish().unwrap();
+        (traces_path, metadata_path)
+    }
+
+    #[test]
+    fn crop_twice() {
+        let (traces_path, _) = npy_files(
+            "crop",
+            array![[0., 1., 2., 3.], [4., 5., 6., 7.]],
+            Array2::zeros((2, 0)),
+        );
+        let mut source = NpySource::open(&traces_path).unwrap();
+        let mut converter = Converter::new();
+        converter.crop(2, 4);
+        for _ in 0..2 {
+            let mut rows = Vec::new();
+            converter
+                .run(&mut source, None, |traces, _| {
+                    rows.push(traces.to_owned());
+                    Ok(())
+                })
+                .unwrap();
+            assert_eq!(rows, vec![array![[2., 3.], [6., 7.]]]);
+        }
+        converter.crop(2, 5);
+        assert!(converter.run(&mut source, None, |_, _| Ok(())).is_err());
+    }
+
+    #[test]
+    fn merge() {
+        let first = npy_files(
+            "first",
+            array![[0., 1., 2.], [3., 4., 5.]],
+            array![[10, 11], [12, 13]],
+        );
+        let second = npy_files("second", array![[6., 7., 8.]], array![[14, 15]]);
+        let open = |(traces, metadata): &(String, String)| {
+            (
+                Box::new(NpySource::open(traces).unwrap()) as Box<dyn TraceSource>,
+                Box::new(NpySource::open(metadata).unwrap()) as Box<dyn MetadataSource>,
+            )
+        };
+        let ((traces_1, metadata_1), (traces_2, metadata_2)) = (open(&first), open(&second));
+        let mut traces = Concat::traces(vec![traces_1, traces_2]).unwrap();
+        let metadata = Concat::metadata(vec![metadata_1, metadata_2]);
+        let output = npy_files("merged", Array2::zeros((0, 1)), Array2::zeros((0, 1)));
+        let mut converter = Converter::new();
+        converter.crop(1, 3);
+        converter.batch_len(2);
+        let written = converter
+            .to_npy(
+                &mut traces,
+                Some(&metadata),
+                &output.0,
+                Some(&output.1),
+                Dtype::F32,
+            )
+            .unwrap();
+        assert_eq!(written, 3);
+        let merged = NpyReader::<f32>::open(&output.0).unwrap();
+        assert_eq!(merged.view(), array![[1., 2.], [4., 5.], [7., 8.]]);
+        let merged = NpyReader::<u8>::open(&output.1).unwrap();
+        assert_eq!(merged.view(), array![[10, 11], [12, 13], [14, 15]]);
+
+        let (narrow, _) = npy_files("narrow", array![[0., 1.]], Array2::zeros((1, 0)));
+        let narrow = Box::new(NpySource::open(&narrow).unwrap()) as Box<dyn TraceSource>;
+        let (traces_1, _) = open(&first);
+        assert!(Concat::traces(vec![traces_1, narrow]).is_err());
+    }
+}
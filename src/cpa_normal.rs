@@ -1,5 +1,14 @@
-use ndarray::{concatenate, Array1, Array2, ArrayView1, ArrayView2, Axis};
+use crate::convergence::ConvergenceMonitor;
+use crate::rank::RankTracker;
+use crate::result::CpaResult;
+use ndarray::{concatenate, Array1, Array2, ArrayBase, ArrayView1, ArrayView2, Axis, Data, Ix2};
 use std::ops::Add;
+use std::sync::Arc;
+
+/// Leakage model of a metadata row and a guess. Closures can capture the
+/// target, e.g. the byte or nibble of the row to attack.
+pub type LeakageModel = Arc<dyn Fn(ArrayView1<usize>, usize) -> usize + Send + Sync>;
+
 pub struct Cpa {
     /* List of internal class variables */
     sum_leakages: Array1<f32>,
@@ -13,10 +22,13 @@ pub struct Cpa {
     corr: Array2<f32>,
     max_corr: Array2<f32>,
     rank_slice: Array2<f32>,
-    leakage_func: fn(ArrayView1<usize>, usize) -> usize,
+    leakage_func: LeakageModel,
     len_samples: usize,
     chunk: usize,
-    rank_traces: usize, // Number of traces to calculate succes rate
+    rank_traces: usize,  // Number of traces to calculate succes rate
+    guess_offset: usize, // First guess covered by this instance (tiled mode)
+    rank_tracker: Option<RankTracker>,
+    monitor: Option<ConvergenceMonitor>,
 }
 
 /* This class implements the CPA algorithm shown in:
@@ -27,12 +39,12 @@ impl Cpa {
         size: usize,
         patch: usize,
         guess_range: i32,
-        f: fn(ArrayView1<usize>, usize) -> usize,
+        f: impl Fn(ArrayView1<usize>, usize) -> usize + Send + Sync + 'static,
     ) -> Self {
         Self {
             len_samples: size,
             chunk: patch,
-            guess_range: guess_range,
+            guess_range,
             sum_leakages: Array1::zeros(size),
             sum2_leakages: Array1::zeros(size),
             sum_keys: Array1::zeros(guess_range as usize),
@@ -42,14 +54,20 @@ impl Cpa {
             corr: Array2::zeros((guess_range as usize, size)),
             max_corr: Array2::zeros((guess_range as usize, 1)),
             rank_slice: Array2::zeros((guess_range as usize, 1)),
-            leakage_func: f,
+            leakage_func: Arc::new(f),
             len_leakages: 0,
             rank_traces: 0,
+            guess_offset: 0,
+            rank_tracker: None,
+            monitor: None,
         }
     }
 
-    pub fn update<T: Copy, U: Copy>(&mut self, trace_patch: Array2<T>, plaintext_patch: Array2<U>)
-    where
+    pub fn update<T: Copy, U: Copy, S: Data<Elem = T>, R: Data<Elem = U>>(
+        &mut self,
+        trace_patch: ArrayBase<S, Ix2>,
+        plaintext_patch: ArrayBase<R, Ix2>,
+    ) where
         f32: From<T>,
         usize: From<U>,
     {
@@ -57,7 +75,7 @@ impl Cpa {
         It accepts trace_patch and plaintext_patch to update them*/
         let tmp_traces = trace_patch.map(|t| f32::from(*t));
         let metadat = plaintext_patch.map(|m| usize::from(*m));
-        self.len_leakages += self.chunk;
+        self.len_leakages += metadat.shape()[0];
         self.update_values(&metadat, &tmp_traces, self.guess_range);
         self.update_key_leakages(tmp_traces, self.guess_range);
     }
@@ -69,15 +87,19 @@ impl Cpa {
         _trace: &Array2<f32>,
         _guess_range: i32,
     ) {
-        for row in 0..self.chunk {
+        /* The last patch of a file may hold fewer rows than chunk */
+        if self.values.shape()[0] != metadata.shape()[0] {
+            self.values = Array2::zeros((metadata.shape()[0], _guess_range as usize));
+        }
+        for row in 0..metadata.shape()[0] {
             for guess in 0.._guess_range {
                 let pass_to_leakage: ArrayView1<usize> = metadata.row(row);
                 self.values[[row, guess as usize]] =
-                    (self.leakage_func)(pass_to_leakage, guess as usize) as f32;
+                    (self.leakage_func)(pass_to_leakage, self.guess_offset + guess as usize) as f32;
             }
         }
 
-        self.cov = self.cov.clone() + self.values.t().dot(_trace);
+        self.cov += &self.values.t().dot(_trace);
     }
 
     pub fn update_key_leakages(&mut self, _trace: Array2<f32>, _guess_range: i32) {
@@ -96,30 +118,36 @@ impl Cpa {
         }
     }
 
-    pub fn update_success<T: Copy, U: Copy>(
+    pub fn update_success<T: Copy, U: Copy, S: Data<Elem = T>, R: Data<Elem = U>>(
         &mut self,
-        trace_patch: Array2<T>,
-        plaintext_patch: Array2<U>,
+        trace_patch: ArrayBase<S, Ix2>,
+        plaintext_patch: ArrayBase<R, Ix2>,
     ) where
         f32: From<T>,
         usize: From<U>,
     {
         /* This function updates the main arrays of the CPA for the success rate*/
         self.update(trace_patch, plaintext_patch);
-        if self.len_leakages % self.rank_traces == 0 {
+        if self.len_leakages.is_multiple_of(self.rank_traces) {
             self.finalize();
             if self.len_leakages == self.rank_traces {
                 self.rank_slice = self.max_corr.clone();
             } else {
                 self.rank_slice = concatenate![Axis(1), self.rank_slice, self.max_corr];
             }
+            if let Some(tracker) = &mut self.rank_tracker {
+                tracker.record(self.len_leakages, self.max_corr.view());
+            }
+            if let Some(monitor) = &mut self.monitor {
+                monitor.check(self.len_leakages, self.max_corr.view());
+            }
         }
     }
 
-    pub fn finalize(&mut self) {
+    pub fn finalize(&mut self) -> CpaResult {
         /* This function finalizes the calculation after
         feeding all stored acc arrays */
-        let cov_n: Array2<f32> = self.cov.clone() / self.len_leakages as f32;
+        let cov_n: Array2<f32> = &self.cov / self.len_leakages as f32;
         let avg_keys: Array1<f32> = self.sum_keys.clone() / self.len_leakages as f32;
         let std_key: Array1<f32> = self.sum2_keys.clone() / self.len_leakages as f32;
         let avg_leakages: Array1<f32> = self.sum_leakages.clone() / self.len_leakages as f32;
@@ -133,12 +161,13 @@ impl Cpa {
 
                 let denominator_2: f32 = std_leakages[x] - (avg_leakages[x] * avg_leakages[x]);
                 if numerator != 0.0 {
-                    self.corr[[i as usize, x]] =
+                    self.corr[[i, x]] =
                         f32::abs(numerator / f32::sqrt(denominator_1 * denominator_2));
                 }
             }
         }
         self.select_max();
+        CpaResult::new(self.corr.view(), self.len_leakages, self.guess_offset)
     }
 
     pub fn select_max(&mut self) {
@@ -163,7 +192,39 @@ impl Cpa {
         self.rank_traces = traces_no;
     }
 
-    pub fn pass_rank(&self) -> ArrayView2<f32> {
+    /// Records the rank of `key` at every success_traces checkpoint.
+    pub fn known_key(&mut self, key: i32) {
+        assert!(
+            (0..self.guess_range).contains(&key),
+            "the key {key} is not a guess in 0..{}",
+            self.guess_range
+        );
+        self.rank_tracker = Some(RankTracker::new(key as usize));
+    }
+
+    pub fn pass_rank_tracker(&self) -> Option<&RankTracker> {
+        self.rank_tracker.as_ref()
+    }
+
+    /// Checks the convergence of the guess at every success_traces checkpoint.
+    pub fn early_stopping(&mut self, monitor: ConvergenceMonitor) {
+        self.monitor = Some(monitor);
+    }
+
+    pub fn converged(&self) -> bool {
+        self.monitor.as_ref().is_some_and(|m| m.converged())
+    }
+
+    pub fn pass_monitor(&self) -> Option<&ConvergenceMonitor> {
+        self.monitor.as_ref()
+    }
+
+    /// Makes this instance cover the guesses `offset..offset + guess_range`.
+    pub fn guess_offset(&mut self, offset: usize) {
+        self.guess_offset = offset;
+    }
+
+    pub fn pass_rank(&self) -> ArrayView2<'_, f32> {
         self.rank_slice.view()
     }
 
@@ -180,7 +241,7 @@ impl Cpa {
                 guess = i;
             }
         }
-        guess
+        guess + self.guess_offset as i32
     }
 }
 
@@ -192,7 +253,8 @@ impl Add for Cpa {
             sum2_leakages: self.sum2_leakages + rhs.sum2_leakages,
             sum_keys: self.sum_keys + rhs.sum_keys,
             sum2_keys: self.sum2_keys + rhs.sum2_keys,
-            values: self.values + rhs.values,
+            /* values only holds the leakages of the last batch */
+            values: rhs.values,
             len_leakages: self.len_leakages + rhs.len_leakages,
             guess_range: rhs.guess_range,
             chunk: rhs.chunk,
@@ -203,6 +265,52 @@ impl Add for Cpa {
             len_samples: rhs.len_samples,
             leakage_func: self.leakage_func,
             rank_traces: self.rank_traces,
+            guess_offset: rhs.guess_offset,
+            rank_tracker: self.rank_tracker,
+            monitor: self.monitor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::s;
+
+    fn leakage_model(value: ArrayView1<usize>, guess: usize) -> usize {
+        (value[0] ^ guess).count_ones() as usize
+    }
+
+    #[test]
+    fn merge_short_batch() {
+        let metadata = Array2::from_shape_fn((13, 1), |(i, _)| (i * 7 + 3) % 16);
+        let traces = Array2::from_shape_fn((13, 4), |(i, j)| {
+            let leakage = leakage_model(metadata.row(i), 5) as f32;
+            leakage * (j as f32 + 1.0) + ((i * 5 + j * 3) % 7) as f32
+        });
+        let mut whole = Cpa::new(4, 13, 16, leakage_model);
+        whole.update(traces.view(), metadata.view());
+        let expected = whole.finalize();
+        let batch = |rows: std::ops::Range<usize>| {
+            let mut cpa = Cpa::new(4, 10, 16, leakage_model);
+            cpa.update(
+                traces.slice(s![rows.clone(), ..]),
+                metadata.slice(s![rows, ..]),
+            );
+            cpa
+        };
+        /* The short batch resized its values to 3 rows */
+        for mut merged in [batch(0..10) + batch(10..13), batch(10..13) + batch(0..10)] {
+            let result = merged.finalize();
+            for guess in 0..16 {
+                assert!((result.score(guess) - expected.score(guess)).abs() < 1e-4);
+            }
         }
     }
+
+    #[test]
+    #[should_panic(expected = "is not a guess")]
+    fn negative_known_key() {
+        Cpa::new(4, 10, 16, leakage_model).known_key(-1);
+    }
 }
@@ -1,4 +1,7 @@
-use ndarray::{concatenate, s, Array1, Array2, ArrayView1, ArrayView2, Axis};
+use crate::convergence::ConvergenceMonitor;
+use crate::rank::RankTracker;
+use crate::result::CpaResult;
+use ndarray::{concatenate, s, Array1, Array2, ArrayView2, Axis};
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use std::ops::Add;
 
@@ -18,6 +21,8 @@ pub struct Cpa_partition {
     rank_slice: Array2<f32>,
     leakage_func: fn(usize, usize) -> usize,
     len_samples: usize,
+    rank_tracker: Option<RankTracker>,
+    monitor: Option<ConvergenceMonitor>,
 }
 
 impl Cpa_partition {
@@ -30,8 +35,8 @@ impl Cpa_partition {
         Self {
             len_samples: size,
             a_l: Array2::zeros((guess_range as usize, size)),
-            target_byte: target_byte,
-            guess_range: guess_range,
+            target_byte,
+            guess_range,
             sum_leakages: Array1::zeros(size),
             sig_leakages: Array1::zeros(size),
             sum_keys: Array1::zeros(guess_range as usize),
@@ -42,6 +47,8 @@ impl Cpa_partition {
             rank_slice: Array2::zeros((guess_range as usize, 1)),
             leakage_func: f,
             len_leakages: 0,
+            rank_tracker: None,
+            monitor: None,
         }
     }
 
@@ -56,39 +63,42 @@ impl Cpa_partition {
     pub fn gen_values(&mut self, metadata: Array1<usize>, _guess_range: i32, _target_key: i32) {
         for guess in 0.._guess_range {
             self.values[guess as usize] =
-                (self.leakage_func)(metadata[_target_key as usize], guess as usize) as usize;
+                (self.leakage_func)(metadata[_target_key as usize], guess as usize);
         }
     }
 
     pub fn go(&mut self, _trace: Array1<usize>, metadata: Array1<usize>, _guess_range: i32) {
         for i in 0..self.len_samples {
-            self.sum_leakages[i] += _trace[i] as usize;
-            self.sig_leakages[i] += (_trace[i] * _trace[i]) as usize;
+            self.sum_leakages[i] += _trace[i];
+            self.sig_leakages[i] += _trace[i] * _trace[i];
         }
 
         for guess in 0.._guess_range {
-            self.sum_keys[guess as usize] += self.values[guess as usize] as usize;
+            self.sum_keys[guess as usize] += self.values[guess as usize];
             self.sig_keys[guess as usize] +=
-                (self.values[guess as usize] * self.values[guess as usize]) as usize;
+                self.values[guess as usize] * self.values[guess as usize];
         }
-        let partition: usize = metadata[self.target_byte as usize] as usize;
+        let partition: usize = metadata[self.target_byte as usize];
         for i in 0..self.len_samples {
-            self.a_l[[partition, i]] += _trace[i] as usize;
+            self.a_l[[partition, i]] += _trace[i];
         }
     }
 
-    pub fn finalize(&mut self) {
+    pub fn finalize(&mut self) -> CpaResult {
         /* This function finalizes the calculation after feeding the
         overall traces */
 
         let shape_p = self.guess_range as usize;
-        let mut p: ndarray::ArrayBase<ndarray::OwnedRepr<usize>, ndarray::Dim<[usize; 2]>> =
-            Array2::zeros((shape_p, shape_p));
-        for i in 0..self.guess_range {
-            for x in 0..self.guess_range {
-                p[[x as usize, i as usize]] = (self.leakage_func)(x as usize, i as usize) as usize;
-            }
-        }
+        /* Leakage of every partition under every guess, guess_range^2 values
+        which are generated in parallel for large guess ranges */
+        let p_values: Vec<f64> = (0..shape_p * shape_p)
+            .into_par_iter()
+            .map(|n| (self.leakage_func)(n / shape_p, n % shape_p) as f64)
+            .collect();
+        let p: Array2<f64> = Array2::from_shape_vec((shape_p, shape_p), p_values).unwrap();
+        /* Sum of the products of partitions and leakages for all guesses and
+        samples at once, in f64 to avoid overflowing on long campaigns */
+        let sum_mult: Array2<f64> = p.t().dot(&self.a_l.map(|a| *a as f64));
 
         for i in 0..self.guess_range {
             let _sigkeys = self.sig_keys[i as usize] as f32 / self.len_leakages as f32;
@@ -98,14 +108,9 @@ impl Cpa_partition {
             let tmp: Vec<f32> = (0..self.len_samples)
                 .into_par_iter()
                 .map(|x| {
-                    let _sumleakages =
-                        self.sum_leakages[x as usize] as f32 / self.len_leakages as f32;
-                    let _sigleakages =
-                        self.sig_leakages[x as usize] as f32 / self.len_leakages as f32;
-                    let slice_a = self.a_l.slice(s![.., x]);
-                    let slice_b = p.slice(s![.., i]);
-                    let summult: i32 = self.sum_mult(slice_a, slice_b);
-                    let upper1: f32 = summult as f32 / self.len_leakages as f32;
+                    let _sumleakages = self.sum_leakages[x] as f32 / self.len_leakages as f32;
+                    let _sigleakages = self.sig_leakages[x] as f32 / self.len_leakages as f32;
+                    let upper1: f32 = (sum_mult[[i as usize, x]] / self.len_leakages as f64) as f32;
                     let upper: f32 = upper1 - (_sumkeys * _sumleakages);
                     let lower2: f32 = _sigleakages - (_sumleakages * _sumleakages);
                     let lower = f32::sqrt(lower1 * lower2);
@@ -113,11 +118,10 @@ impl Cpa_partition {
                 })
                 .collect();
 
-            for z in 0..self.len_samples {
-                self.corr[[i as usize, z]] = tmp[z];
-            }
+            self.corr.row_mut(i as usize).assign(&Array1::from(tmp));
         }
         self.calculation();
+        CpaResult::new(self.corr.view(), self.len_leakages, 0)
     }
 
     pub fn calculation(&mut self) {
@@ -137,9 +141,42 @@ impl Cpa_partition {
             self.max_corr[[i as usize, 0]] = *max_value;
         }
         self.rank_slice = concatenate![Axis(1), self.rank_slice, self.max_corr];
+        if let Some(tracker) = &mut self.rank_tracker {
+            tracker.record(self.len_leakages, self.max_corr.view());
+        }
+        if let Some(monitor) = &mut self.monitor {
+            monitor.check(self.len_leakages, self.max_corr.view());
+        }
+    }
+
+    /// Records the rank of `key` every time the CPA is finalized.
+    pub fn known_key(&mut self, key: i32) {
+        assert!(
+            (0..self.guess_range).contains(&key),
+            "the key {key} is not a guess in 0..{}",
+            self.guess_range
+        );
+        self.rank_tracker = Some(RankTracker::new(key as usize));
+    }
+
+    pub fn pass_rank_tracker(&self) -> Option<&RankTracker> {
+        self.rank_tracker.as_ref()
+    }
+
+    /// Checks the convergence of the guess at every finalize call.
+    pub fn early_stopping(&mut self, monitor: ConvergenceMonitor) {
+        self.monitor = Some(monitor);
+    }
+
+    pub fn converged(&self) -> bool {
+        self.monitor.as_ref().is_some_and(|m| m.converged())
     }
 
-    pub fn pass_rank(&self) -> ArrayView2<f32> {
+    pub fn pass_monitor(&self) -> Option<&ConvergenceMonitor> {
+        self.monitor.as_ref()
+    }
+
+    pub fn pass_rank(&self) -> ArrayView2<'_, f32> {
         self.rank_slice.slice(s![.., 1..])
     }
 
@@ -159,10 +196,6 @@ impl Cpa_partition {
 
         guess
     }
-
-    fn sum_mult(&self, a: ArrayView1<usize>, b: ArrayView1<usize>) -> i32 {
-        a.dot(&b) as i32
-    }
 }
 
 impl Add for Cpa_partition {
@@ -183,6 +216,8 @@ impl Add for Cpa_partition {
             rank_slice: self.rank_slice,
             len_samples: rhs.len_samples,
             leakage_func: self.leakage_func,
+            rank_tracker: self.rank_tracker,
+            monitor: self.monitor,
         }
     }
 }
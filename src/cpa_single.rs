@@ -1,3 +1,6 @@
+use crate::convergence::ConvergenceMonitor;
+use crate::rank::RankTracker;
+use crate::result::CpaResult;
 use ndarray::{concatenate, s, Array1, Array2, ArrayView2, Axis};
 use std::ops::Add;
 
@@ -18,6 +21,8 @@ pub struct Cpa<T> {
     leakage_func: fn(T, usize) -> f64,
     len_samples: usize,
     rank_traces: usize, // Number of traces to calculate succes rate
+    rank_tracker: Option<RankTracker>,
+    monitor: Option<ConvergenceMonitor>,
 }
 
 /* This class implements the CPA algorithm shown in:
@@ -27,7 +32,7 @@ impl<T: Clone> Cpa<T> {
     pub fn new(size: usize, guess_range: i32, f: fn(T, usize) -> f64) -> Self {
         Self {
             len_samples: size,
-            guess_range: guess_range,
+            guess_range,
             sum_leakages: Array1::zeros(size),
             sig_leakages: Array1::zeros(size),
             sum_keys: Array1::zeros(guess_range as usize),
@@ -41,6 +46,8 @@ impl<T: Clone> Cpa<T> {
             len_leakages: 0,
             rank_traces: 0,
             init_rank: false, // traces_patch: Array2::zeros((patch, size)),
+            rank_tracker: None,
+            monitor: None,
         }
     }
 
@@ -49,7 +56,7 @@ impl<T: Clone> Cpa<T> {
         f64: From<U>,
     {
         let mut trace_tmp: Array1<f64> = Array1::zeros(self.len_samples);
-        for i in 0..self.len_samples as usize {
+        for i in 0..self.len_samples {
             trace_tmp[i] = trace[i].clone().into();
         }
 
@@ -74,7 +81,7 @@ impl<T: Clone> Cpa<T> {
         for column in 0..self.len_samples {
             for row in 0..self.guess_range {
                 self.cov[[row as usize, column]] +=
-                    self.values[row as usize] as f64 * sample_trace[column];
+                    self.values[row as usize] * sample_trace[column];
             }
         }
 
@@ -111,12 +118,12 @@ impl<T: Clone> Cpa<T> {
         }
     }
 
-    pub fn finalize(&mut self) {
+    pub fn finalize(&mut self) -> CpaResult {
         // println!("{:?}", self.cov);
         /* This function finalizes the calculation after feeding the
         overall traces */
 
-        for i in 0..self.guess_range as i32 {
+        for i in 0..self.guess_range {
             for x in 0..self.len_samples {
                 let upper: f32 = (self.cov[[i as usize, x]] as f32 / self.len_leakages as f32)
                     - ((self.sum_keys[i as usize] as f32 / self.len_leakages as f32)
@@ -126,14 +133,15 @@ impl<T: Clone> Cpa<T> {
                     - ((self.sum_keys[i as usize] as f32 / self.len_leakages as f32)
                         * (self.sum_keys[i as usize] as f32 / self.len_leakages as f32));
 
-                let lower_2 = (self.sig_leakages[x as usize] as f32 / self.len_leakages as f32)
-                    - ((self.sum_leakages[x as usize] as f32 / self.len_leakages as f32)
-                        * (self.sum_leakages[x as usize] as f32 / self.len_leakages as f32));
+                let lower_2 = (self.sig_leakages[x] as f32 / self.len_leakages as f32)
+                    - ((self.sum_leakages[x] as f32 / self.len_leakages as f32)
+                        * (self.sum_leakages[x] as f32 / self.len_leakages as f32));
 
                 self.corr[[i as usize, x]] = f32::abs(upper / f32::sqrt(lower_1 * lower_2));
             }
         }
         self.calculation();
+        CpaResult::new(self.corr.view(), self.len_leakages, 0)
     }
 
     pub fn calculation(&mut self) {
@@ -158,7 +166,7 @@ impl<T: Clone> Cpa<T> {
         self.rank_traces = traces_no;
     }
 
-    pub fn pass_rank(&self) -> ArrayView2<f32> {
+    pub fn pass_rank(&self) -> ArrayView2<'_, f32> {
         self.rank_slice.slice(s![.., 1..])
     }
 
@@ -186,6 +194,39 @@ impl<T: Clone> Cpa<T> {
         } else {
             self.rank_slice = concatenate![Axis(1), self.rank_slice.clone(), self.max_corr];
         }
+        if let Some(tracker) = &mut self.rank_tracker {
+            tracker.record(self.len_leakages, self.max_corr.view());
+        }
+        if let Some(monitor) = &mut self.monitor {
+            monitor.check(self.len_leakages, self.max_corr.view());
+        }
+    }
+
+    /// Records the rank of `key` at every update_success call.
+    pub fn known_key(&mut self, key: i32) {
+        assert!(
+            (0..self.guess_range).contains(&key),
+            "the key {key} is not a guess in 0..{}",
+            self.guess_range
+        );
+        self.rank_tracker = Some(RankTracker::new(key as usize));
+    }
+
+    pub fn pass_rank_tracker(&self) -> Option<&RankTracker> {
+        self.rank_tracker.as_ref()
+    }
+
+    /// Checks the convergence of the guess at every update_success call.
+    pub fn early_stopping(&mut self, monitor: ConvergenceMonitor) {
+        self.monitor = Some(monitor);
+    }
+
+    pub fn converged(&self) -> bool {
+        self.monitor.as_ref().is_some_and(|m| m.converged())
+    }
+
+    pub fn pass_monitor(&self) -> Option<&ConvergenceMonitor> {
+        self.monitor.as_ref()
     }
 
     pub fn pass_succes(&self) -> Array2<f32> {
@@ -212,6 +253,8 @@ impl<T> Add for Cpa<T> {
             len_samples: self.len_samples,
             rank_traces: self.rank_traces,
             init_rank: self.init_rank,
+            rank_tracker: self.rank_tracker,
+            monitor: self.monitor,
         }
     }
 }
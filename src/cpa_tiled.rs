@@ -0,0 +1,137 @@
+use crate::cpa_normal::Cpa;
+use crate::result::CpaResult;
+use crate::tools::write_array;
+use ndarray::{s, Array1, Array2, ArrayView1, ArrayView2};
+
+#[allow(non_camel_case_types)]
+pub struct Cpa_tiled {
+    /* List of internal class variables */
+    len_samples: usize,
+    chunk: usize,
+    guess_range: usize,
+    tile: usize,
+    leakage_func: fn(ArrayView1<usize>, usize) -> usize,
+    max_corr: Array2<f32>,
+    peaks: Array1<usize>,
+    corr_dir: Option<String>,
+    len_leakages: usize,
+    tiles_per_pass: usize,
+}
+
+/* This class runs cpa_normal::Cpa over the guess space in tiles of `tile`
+guesses, so that only tile x len_samples correlations are allocated at once
+instead of guess_range x len_samples. Every tile consumes the whole campaign:
+the batches are requested (and read from disk) once per pass, and a pass
+covers `tiles_per_pass` tiles, 1 by default. Raising it divides the number of
+reads of the campaign at the cost of tiles_per_pass x tile x len_samples
+correlations in memory. */
+
+impl Cpa_tiled {
+    pub fn new(
+        size: usize,
+        patch: usize,
+        guess_range: usize,
+        tile: usize,
+        f: fn(ArrayView1<usize>, usize) -> usize,
+    ) -> Self {
+        assert!(tile > 0, "the tile must hold at least one guess");
+        Self {
+            len_samples: size,
+            chunk: patch,
+            guess_range,
+            tile,
+            leakage_func: f,
+            max_corr: Array2::zeros((guess_range, 1)),
+            peaks: Array1::zeros(guess_range),
+            corr_dir: None,
+            len_leakages: 0,
+            tiles_per_pass: 1,
+        }
+    }
+
+    /// Writes the correlation of every tile to `dir/corr_<first guess>.npy`
+    /// instead of only keeping the per-guess maxima.
+    pub fn stream_corr(&mut self, dir: &str) {
+        self.corr_dir = Some(dir.to_string());
+    }
+
+    /// Processes `tiles` tiles per pass over the batches.
+    pub fn tiles_per_pass(&mut self, tiles: usize) {
+        assert!(tiles > 0, "tiles_per_pass must be at least 1");
+        self.tiles_per_pass = tiles;
+    }
+
+    pub fn run<T: Copy, U: Copy, I>(&mut self, batches: impl Fn() -> I)
+    where
+        I: Iterator<Item = (Array2<T>, Array2<U>)>,
+        f32: From<T>,
+        usize: From<U>,
+    {
+        let len_pass = self.tile * self.tiles_per_pass;
+        for first in (0..self.guess_range).step_by(len_pass) {
+            let end = (first + len_pass).min(self.guess_range);
+            let mut tiles: Vec<(usize, Cpa)> = (first..end)
+                .step_by(self.tile)
+                .map(|start| {
+                    let len_tile = self.tile.min(end - start);
+                    let mut cpa = Cpa::new(
+                        self.len_samples,
+                        self.chunk,
+                        len_tile as i32,
+                        self.leakage_func,
+                    );
+                    cpa.guess_offset(start);
+                    (start, cpa)
+                })
+                .collect();
+            for (trace_patch, plaintext_patch) in batches() {
+                for (_, cpa) in tiles.iter_mut() {
+                    cpa.update(trace_patch.view(), plaintext_patch.view());
+                }
+            }
+            for (start, mut cpa) in tiles {
+                self.finish_tile(start, &mut cpa);
+            }
+        }
+    }
+
+    /// Copies the maxima of the tile starting at guess `start`.
+    fn finish_tile(&mut self, start: usize, cpa: &mut Cpa) {
+        let result = cpa.finalize();
+        let range_tile = start..start + result.pass_scores().len();
+        self.max_corr
+            .slice_mut(s![range_tile.clone(), 0])
+            .assign(&result.pass_scores());
+        self.peaks
+            .slice_mut(s![range_tile])
+            .assign(&result.pass_peaks());
+        self.len_leakages = result.significance().traces_no();
+        if let Some(dir) = &self.corr_dir {
+            let corr = cpa.pass_corr_array();
+            write_array(&format!("{dir}/corr_{start}.npy"), corr.view());
+        }
+    }
+
+    /// Ranked result over the whole guess space.
+    pub fn pass_result(&self) -> CpaResult {
+        CpaResult::from_maxima(
+            self.max_corr.column(0).to_owned(),
+            self.peaks.clone(),
+            self.len_leakages,
+            0,
+        )
+    }
+
+    pub fn pass_max_corr(&self) -> ArrayView2<'_, f32> {
+        self.max_corr.view()
+    }
+
+    pub fn pass_peaks(&self) -> ArrayView1<'_, usize> {
+        self.peaks.view()
+    }
+
+    /// Best guess over the whole guess space, None when no guess correlates.
+    pub fn pass_guess(&self) -> Option<usize> {
+        self.pass_result().best()
+    }
+}
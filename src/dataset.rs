@@ -0,0 +1,452 @@
+use crate::npy::{NpyElement, NpyReader, NpySource};
+use ndarray::{concatenate, Array2, ArrayView2, Axis};
+use num_traits::AsPrimitive;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/* A campaign stored as pairs of trace and metadata NPY files, e.g.
+l/0.npy, p/0.npy, l/1.npy, p/1.npy, ... The files are memory-mapped and seen
+as a single sequence of traces: batches are cut in that sequence and may
+span two files, so drivers never deal with file indices. Traces stored in
+other formats (TRS, oscilloscope captures, NPY of any element type) are
+TraceSources, read as f32 samples, and are paired with an NPY metadata file
+by SourceDataset. Metadata stored in other formats (TRS data bytes, CSV)
+are MetadataSources. Concat sees several sources as one. */
+
+pub struct Dataset<T: NpyElement, U: NpyElement> {
+    files: Vec<(NpyReader<T>, NpyReader<U>)>,
+    /// Index of the first trace of every file.
+    starts: Vec<usize>,
+    len_traces: usize,
+}
+
+impl<T: NpyElement, U: NpyElement> Dataset<T, U> {
+    /// Opens the (traces, metadata) file pairs, in order.
+    pub fn open(pairs: &[(String, String)]) -> Result<Self> {
+        let mut files: Vec<(NpyReader<T>, NpyReader<U>)> = Vec::new();
+        let mut starts = Vec::new();
+        let mut len_traces = 0;
+        for (dir_l, dir_p) in pairs {
+            let leakages = NpyReader::<T>::open(dir_l)?;
+            let metadata = NpyReader::<U>::open(dir_p)?;
+            if leakages.len_traces() != metadata.len_traces() {
+                return Err(mismatch(format!(
+                    "{dir_l} holds {} traces but {dir_p} holds {} rows",
+                    leakages.len_traces(),
+                    metadata.len_traces()
+                )));
+            }
+            if let Some((first_l, first_p)) = files.first() {
+                if leakages.len_samples() != first_l.len_samples()
+                    || metadata.len_samples() != first_p.len_samples()
+                {
+                    return Err(mismatch(format!(
+                        "{dir_l} or {dir_p} differs in width from the first pair"
+                    )));
+                }
+            }
+            starts.push(len_traces);
+            len_traces += leakages.len_traces();
+            files.push((leakages, metadata));
+        }
+        Ok(Self {
+            files,
+            starts,
+            len_traces,
+        })
+    }
+
+    /// Finds the file pairs in `folder` from patterns where `{i}` stands for
+    /// the file number, e.g. "l/{i}.npy" and "p/{i}.npy". The numbers found
+    /// for the traces are sorted and every one needs a metadata file.
+    pub fn discover(folder: &str, traces_pattern: &str, metadata_pattern: &str) -> Result<Self> {
+        let traces_path = Path::new(folder).join(traces_pattern);
+        let dir = traces_path.parent().unwrap_or(Path::new(folder));
+        let name = traces_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let (prefix, suffix) = name
+            .split_once("{i}")
+            .ok_or_else(|| mismatch(format!("{traces_pattern} does not contain {{i}}")))?;
+        let mut numbers: Vec<usize> = fs::read_dir(dir)?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let file_name = entry.file_name();
+                let number = file_name
+                    .to_str()?
+                    .strip_prefix(prefix)?
+                    .strip_suffix(suffix)?;
+                number.parse().ok()
+            })
+            .collect();
+        numbers.sort_unstable();
+        if numbers.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no file matches {}", traces_path.display()),
+            ));
+        }
+        let pairs: Vec<(String, String)> = numbers
+            .iter()
+            .map(|i| {
+                let path = |pattern: &str| {
+                    Path::new(folder)
+                        .join(pattern.replace("{i}", &i.to_string()))
+                        .to_string_lossy()
+                        .into_owned()
+                };
+                (path(traces_pattern), path(metadata_pattern))
+            })
+            .collect();
+        Self::open(&pairs)
+    }
+
+    /// Restricts the traces of every file to the samples start..end.
+    pub fn window(&mut self, start: usize, end: usize) {
+        for (leakages, _) in self.files.iter_mut() {
+            leakages.window(start, end);
+        }
+    }
+
+    pub fn len_traces(&self) -> usize {
+        self.len_traces
+    }
+
+    /// Number of samples per trace, within the window.
+    pub fn len_samples(&self) -> usize {
+        self.files.first().map_or(0, |(l, _)| l.len_samples())
+    }
+
+    /// Number of samples per trace, ignoring the window.
+    pub fn width(&self) -> usize {
+        self.files.first().map_or(0, |(l, _)| l.header().shape.1)
+    }
+
+    /// Number of metadata values per trace.
+    pub fn len_metadata(&self) -> usize {
+        self.files.first().map_or(0, |(_, p)| p.len_samples())
+    }
+
+    pub fn len_files(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Traces start..start + len of the whole dataset with their metadata.
+    pub fn batch(&self, start: usize, len: usize) -> (Array2<T>, Array2<U>) {
+        let end = (start + len).min(self.len_traces);
+        let mut traces: Vec<ArrayView2<T>> = Vec::new();
+        let mut metadata: Vec<ArrayView2<U>> = Vec::new();
+        for ((leakages, plaintext), first) in self.files.iter().zip(&self.starts) {
+            let last = first + leakages.len_traces();
+            if last <= start || *first >= end {
+                continue;
+            }
+            let from = start.max(*first) - first;
+            let to = end.min(last) - first;
+            traces.push(leakages.chunk(from, to - from));
+            metadata.push(plaintext.chunk(from, to - from));
+        }
+        match traces.len() {
+            0 => (
+                Array2::from_shape_vec((0, self.len_samples()), Vec::new()).unwrap(),
+                Array2::from_shape_vec((0, self.len_metadata()), Vec::new()).unwrap(),
+            ),
+            1 => (traces[0].to_owned(), metadata[0].to_owned()),
+            _ => (
+                concatenate(Axis(0), &traces).unwrap(),
+                concatenate(Axis(0), &metadata).unwrap(),
+            ),
+        }
+    }
+
+    /// Batches of `len` traces in order.
+    pub fn batches(&self, len: usize) -> impl Iterator<Item = (Array2<T>, Array2<U>)> + '_ {
+        (0..self.len_traces)
+            .step_by(len)
+            .map(move |start| self.batch(start, len))
+    }
+}
+
+impl<T: NpyElement + Send + Sync, U: NpyElement + Send + Sync> Dataset<T, U> {
+    /// Batches of `len` traces read in parallel, in no particular order.
+    pub fn par_batches(
+        &self,
+        len: usize,
+    ) -> impl ParallelIterator<Item = (Array2<T>, Array2<U>)> + '_ {
+        let starts: Vec<usize> = (0..self.len_traces).step_by(len).collect();
+        starts
+            .into_par_iter()
+            .map(move |start| self.batch(start, len))
+    }
+}
+
+/// Traces read in batches of f32 samples, whatever the storage format.
+pub trait TraceSource {
+    fn len_traces(&self) -> usize;
+
+    /// Number of samples per trace, within the window.
+    fn len_samples(&self) -> usize;
+
+    /// Number of samples per trace, ignoring the window.
+    fn width(&self) -> usize;
+
+    /// Restricts the traces to the samples start..end.
+    fn window(&mut self, start: usize, end: usize);
+
+    /// Traces start..start + len, clipped to the source.
+    fn batch(&self, start: usize, len: usize) -> Array2<f32>;
+}
+
+impl TraceSource for NpySource {
+    fn len_traces(&self) -> usize {
+        NpySource::len_traces(self)
+    }
+
+    fn len_samples(&self) -> usize {
+        NpySource::len_samples(self)
+    }
+
+    fn width(&self) -> usize {
+        self.header().shape.1
+    }
+
+    fn window(&mut self, start: usize, end: usize) {
+        NpySource::window(self, start, end);
+    }
+
+    fn batch(&self, start: usize, len: usize) -> Array2<f32> {
+        /* Every NPY element type converts to f32 */
+        NpySource::batch(self, start, len).unwrap()
+    }
+}
+
+impl<T: NpyElement + AsPrimitive<f32>, U: NpyElement> TraceSource for Dataset<T, U> {
+    fn len_traces(&self) -> usize {
+        Dataset::len_traces(self)
+    }
+
+    fn len_samples(&self) -> usize {
+        Dataset::len_samples(self)
+    }
+
+    fn width(&self) -> usize {
+        Dataset::width(self)
+    }
+
+    fn window(&mut self, start: usize, end: usize) {
+        Dataset::window(self, start, end);
+    }
+
+    fn batch(&self, start: usize, len: usize) -> Array2<f32> {
+        Dataset::batch(self, start, len).0.mapv(|t| t.as_())
+    }
+}
+
+/// Metadata bytes read in batches, whatever the storage format.
+pub trait MetadataSource {
+    fn len_traces(&self) -> usize;
+
+    /// Rows start..start + len, clipped to the source. Rows that do not
+    /// hold bytes are an error.
+    fn metadata(&self, start: usize, len: usize) -> Result<Array2<u8>>;
+}
+
+impl MetadataSource for NpySource {
+    fn len_traces(&self) -> usize {
+        NpySource::len_traces(self)
+    }
+
+    fn metadata(&self, start: usize, len: usize) -> Result<Array2<u8>> {
+        NpySource::batch(self, start, len)
+    }
+}
+
+impl<T: NpyElement> MetadataSource for Dataset<T, u8> {
+    fn len_traces(&self) -> usize {
+        Dataset::len_traces(self)
+    }
+
+    fn metadata(&self, start: usize, len: usize) -> Result<Array2<u8>> {
+        Ok(Dataset::batch(self, start, len).1)
+    }
+}
+
+/// Sources seen as a single sequence of traces, in order: trace sources of
+/// the same width or metadata sources, e.g. to convert several campaigns
+/// to a single file.
+pub struct Concat<S: ?Sized> {
+    sources: Vec<Box<S>>,
+    /// Index of the first trace of every source.
+    starts: Vec<usize>,
+    len_traces: usize,
+}
+
+impl<S: ?Sized> Concat<S> {
+    fn from_lens(sources: Vec<Box<S>>, len_traces: impl Fn(&S) -> usize) -> Self {
+        let mut starts = Vec::with_capacity(sources.len());
+        let mut total = 0;
+        for source in &sources {
+            starts.push(total);
+            total += len_traces(source);
+        }
+        Self {
+            sources,
+            starts,
+            len_traces: total,
+        }
+    }
+
+    pub fn len_sources(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// The parts of the traces start..start + len (clipped) held by every
+    /// source, as (source, first trace in the source, number of traces).
+    fn parts(&self, start: usize, len: usize) -> Vec<(&S, usize, usize)> {
+        let end = (start + len).min(self.len_traces);
+        let next = self.starts.iter().skip(1).chain([&self.len_traces]);
+        self.sources
+            .iter()
+            .zip(&self.starts)
+            .zip(next)
+            .filter(|((_, first), last)| **first < end && **last > start)
+            .map(|((source, first), last)| {
+                let from = start.max(*first);
+                (&**source, from - first, end.min(*last) - from)
+            })
+            .collect()
+    }
+}
+
+impl<'a> Concat<dyn TraceSource + 'a> {
+    pub fn traces(sources: Vec<Box<dyn TraceSource + 'a>>) -> Result<Self> {
+        if let Some(first) = sources.first() {
+            if sources.iter().any(|s| s.width() != first.width()) {
+                return Err(mismatch("the trace sources differ in width".to_string()));
+            }
+        }
+        Ok(Self::from_lens(sources, |s| s.len_traces()))
+    }
+}
+
+impl TraceSource for Concat<dyn TraceSource + '_> {
+    fn len_traces(&self) -> usize {
+        self.len_traces
+    }
+
+    fn len_samples(&self) -> usize {
+        self.sources.first().map_or(0, |s| s.len_samples())
+    }
+
+    fn width(&self) -> usize {
+        self.sources.first().map_or(0, |s| s.width())
+    }
+
+    fn window(&mut self, start: usize, end: usize) {
+        for source in self.sources.iter_mut() {
+            source.window(start, end);
+        }
+    }
+
+    fn batch(&self, start: usize, len: usize) -> Array2<f32> {
+        let batches: Vec<Array2<f32>> = self
+            .parts(start, len)
+            .into_iter()
+            .map(|(source, first, len)| source.batch(first, len))
+            .collect();
+        match batches.len() {
+            0 => Array2::zeros((0, self.len_samples())),
+            1 => batches.into_iter().next().unwrap(),
+            _ => concatenate(
+                Axis(0),
+                &batches.iter().map(|b| b.view()).collect::<Vec<_>>(),
+            )
+            .unwrap(),
+        }
+    }
+}
+
+impl<'a> Concat<dyn MetadataSource + 'a> {
+    pub fn metadata(sources: Vec<Box<dyn MetadataSource + 'a>>) -> Self {
+        Self::from_lens(sources, |s| s.len_traces())
+    }
+}
+
+impl MetadataSource for Concat<dyn MetadataSource + '_> {
+    fn len_traces(&self) -> usize {
+        self.len_traces
+    }
+
+    /// Rows of sources differing in width are an error.
+    fn metadata(&self, start: usize, len: usize) -> Result<Array2<u8>> {
+        let rows = self
+            .parts(start, len)
+            .into_iter()
+            .map(|(source, first, len)| source.metadata(first, len))
+            .collect::<Result<Vec<Array2<u8>>>>()?;
+        match rows.len() {
+            0 => Ok(Array2::zeros((0, 0))),
+            1 => Ok(rows.into_iter().next().unwrap()),
+            _ => concatenate(Axis(0), &rows.iter().map(|r| r.view()).collect::<Vec<_>>())
+                .map_err(|_| mismatch("the metadata sources differ in width".to_string())),
+        }
+    }
+}
+
+/// Traces of a TraceSource with their metadata rows from an NPY file.
+pub struct SourceDataset<S: TraceSource, U: NpyElement> {
+    traces: S,
+    metadata: NpyReader<U>,
+}
+
+impl<S: TraceSource, U: NpyElement> SourceDataset<S, U> {
+    pub fn new(traces: S, metadata: &str) -> Result<Self> {
+        let metadata = NpyReader::<U>::open(metadata)?;
+        if traces.len_traces() != metadata.len_traces() {
+            return Err(mismatch(format!(
+                "{} traces but {} metadata rows",
+                traces.len_traces(),
+                metadata.len_traces()
+            )));
+        }
+        Ok(Self { traces, metadata })
+    }
+
+    /// Restricts the traces to the samples start..end.
+    pub fn window(&mut self, start: usize, end: usize) {
+        self.traces.window(start, end);
+    }
+
+    pub fn len_traces(&self) -> usize {
+        self.traces.len_traces()
+    }
+
+    pub fn len_samples(&self) -> usize {
+        self.traces.len_samples()
+    }
+
+    pub fn len_metadata(&self) -> usize {
+        self.metadata.len_samples()
+    }
+
+    pub fn batch(&self, start: usize, len: usize) -> (Array2<f32>, Array2<U>) {
+        (
+            self.traces.batch(start, len),
+            self.metadata.chunk(start, len).to_owned(),
+        )
+    }
+
+    /// Batches of `len` traces in order.
+    pub fn batches(&self, len: usize) -> impl Iterator<Item = (Array2<f32>, Array2<U>)> + '_ {
+        (0..self.len_traces())
+            .step_by(len)
+            .map(move |start| self.batch(start, len))
+    }
+}
+
+fn mismatch(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,176 @@
+use crate::poi::Poi;
+use crate::rank::RankTracker;
+use crate::result::CpaResult;
+use crate::stats::difference_p_value;
+use ndarray::{Array1, Array2, ArrayView2};
+use ndarray_npy::NpzWriter;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Error, Result, Write};
+use std::time::Duration;
+
+/* Archive of an attack: a self-describing NPZ bundle with the arrays of every
+attacked byte and a JSON summary of the run. For byte b the bundle holds
+max_corr_b (maximum correlation per guess), peaks_b (sample of the maximum),
+and when recorded corr_b (guess x sample correlations) and rank_b (one row
+per checkpoint: traces, rank, correct key correlation, best wrong key
+correlation). pois holds the points of interest the traces were reduced to.
+The summary gives the configuration, the number of traces, the timings and
+the guess, score, peak, runner-up margin and the p-value of that margin
+for every byte. */
+
+struct ByteExport {
+    result: CpaResult,
+    corr: Option<Array2<f32>>,
+    rank: Option<RankTracker>,
+}
+
+#[derive(Default)]
+pub struct Export {
+    config: Map<String, Value>,
+    traces_no: usize,
+    timings: Vec<(String, Duration)>,
+    pois: Option<Vec<usize>>,
+    bytes: BTreeMap<usize, ByteExport>,
+}
+
+impl Export {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a configuration entry, e.g. ("patch", 500) or ("model", "hw").
+    pub fn config(&mut self, key: &str, value: impl Into<Value>) {
+        self.config.insert(key.to_string(), value.into());
+    }
+
+    /// Number of traces the attack used.
+    pub fn traces(&mut self, traces_no: usize) {
+        self.traces_no = traces_no;
+    }
+
+    /// Records the duration of a step of the run, e.g. ("attack", elapsed).
+    pub fn timing(&mut self, step: &str, duration: Duration) {
+        self.timings.push((step.to_string(), duration));
+    }
+
+    pub fn pois(&mut self, pois: &Poi) {
+        self.pois = Some(pois.indices().to_vec());
+    }
+
+    /// Records the result of the attack on `byte`.
+    pub fn result(&mut self, byte: usize, result: &CpaResult) {
+        self.bytes.insert(
+            byte,
+            ByteExport {
+                result: result.clone(),
+                corr: None,
+                rank: None,
+            },
+        );
+    }
+
+    /// Records the correlation array of `byte`, whose result must be recorded.
+    pub fn correlation(&mut self, byte: usize, corr: ArrayView2<f32>) {
+        self.byte_mut(byte).corr = Some(corr.to_owned());
+    }
+
+    /// Records the rank evolution of `byte`, whose result must be recorded.
+    pub fn rank_evolution(&mut self, byte: usize, tracker: &RankTracker) {
+        self.byte_mut(byte).rank = Some(tracker.clone());
+    }
+
+    fn byte_mut(&mut self, byte: usize) -> &mut ByteExport {
+        self.bytes
+            .get_mut(&byte)
+            .unwrap_or_else(|| panic!("no result recorded for byte {byte}"))
+    }
+
+    /// Best guess of every byte, None when no guess correlates.
+    pub fn guesses(&self) -> Vec<Option<usize>> {
+        self.bytes.values().map(|b| b.result.best()).collect()
+    }
+
+    pub fn summary(&self) -> Value {
+        let bytes: Vec<Value> = self
+            .bytes
+            .iter()
+            .map(|(byte, b)| {
+                let result = &b.result;
+                let Some(&guess) = result.ranking().first() else {
+                    return json!({ "byte": byte, "guess": null });
+                };
+                let margin_p_value = result.ranking().get(1).map(|second| {
+                    difference_p_value(
+                        result.score(guess) as f64,
+                        result.score(*second) as f64,
+                        result.significance().traces_no(),
+                    )
+                });
+                let mut entry = json!({
+                    "byte": byte,
+                    "guess": result.best(),
+                    "score": result.score(guess),
+                    "peak": result.peak(guess),
+                    "margin": result.margins().first(),
+                    "margin_p_value": margin_p_value,
+                    "top": result.top_k(5),
+                    "rejected": result.rejected().len(),
+                });
+                if let Some(rank) = &b.rank {
+                    entry["correct_key"] = json!(rank.correct_key());
+                    entry["rank"] = json!(rank.last_rank());
+                    entry["traces_to_rank_one"] = json!(rank.traces_to_rank_one());
+                }
+                entry
+            })
+            .collect();
+        let timings: Map<String, Value> = self
+            .timings
+            .iter()
+            .map(|(step, d)| (step.clone(), json!(d.as_secs_f64())))
+            .collect();
+        json!({
+            "config": self.config,
+            "traces": self.traces_no,
+            "timings": timings,
+            "pois": self.pois,
+            "bytes": bytes,
+        })
+    }
+
+    pub fn write_json(&self, path: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, &self.summary())?;
+        writer.flush()
+    }
+
+    pub fn write_npz(&self, path: &str) -> Result<()> {
+        let mut npz = NpzWriter::new_compressed(File::create(path)?);
+        let to_u64 = |values: &[usize]| Array1::from_iter(values.iter().map(|v| *v as u64));
+        for (byte, b) in &self.bytes {
+            let result = &b.result;
+            npz.add_array(format!("max_corr_{byte}"), &result.pass_scores())
+                .map_err(Error::other)?;
+            npz.add_array(
+                format!("peaks_{byte}"),
+                &to_u64(&result.pass_peaks().to_vec()),
+            )
+            .map_err(Error::other)?;
+            if let Some(corr) = &b.corr {
+                npz.add_array(format!("corr_{byte}"), corr)
+                    .map_err(Error::other)?;
+            }
+            if let Some(rank) = &b.rank {
+                npz.add_array(format!("rank_{byte}"), &rank.pass_table())
+                    .map_err(Error::other)?;
+            }
+        }
+        if let Some(pois) = &self.pois {
+            npz.add_array("pois", &to_u64(pois)).map_err(Error::other)?;
+        }
+        npz.finish().map_err(Error::other)?;
+        Ok(())
+    }
+}
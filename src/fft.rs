@@ -0,0 +1,67 @@
+use num_complex::Complex64;
+use std::f64::consts::PI;
+
+/* In-place iterative radix-2 FFT, the length of the buffer must be a power
+of two. The inverse transform is scaled by 1/n. */
+
+pub fn fft(buffer: &mut [Complex64], inverse: bool) {
+    let n = buffer.len();
+    assert!(n.is_power_of_two(), "FFT length must be a power of two");
+    /* Bit reversal permutation */
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            buffer.swap(i, j);
+        }
+    }
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let w_len = Complex64::from_polar(1.0, sign * 2.0 * PI / len as f64);
+        for start in (0..n).step_by(len) {
+            let mut w = Complex64::new(1.0, 0.0);
+            for k in 0..len / 2 {
+                let u = buffer[start + k];
+                let v = buffer[start + k + len / 2] * w;
+                buffer[start + k] = u + v;
+                buffer[start + k + len / 2] = u - v;
+                w *= w_len;
+            }
+        }
+        len <<= 1;
+    }
+    if inverse {
+        for x in buffer.iter_mut() {
+            *x /= n as f64;
+        }
+    }
+}
+
+/// Zero-padded spectrum of a real signal over `n` points.
+pub fn real_fft(signal: &[f64], n: usize) -> Vec<Complex64> {
+    let mut buffer = vec![Complex64::new(0.0, 0.0); n];
+    for (b, s) in buffer.iter_mut().zip(signal) {
+        b.re = *s;
+    }
+    fft(&mut buffer, false);
+    buffer
+}
+
+/// Cross-correlation c[k] = sum_j signal[j + k] * pattern[j] for every
+/// k in 0..=signal.len() - pattern.len(), given the pattern spectrum over
+/// `n >= signal.len()` points.
+pub fn cross_correlation(signal: &[f64], pattern_spectrum: &[Complex64]) -> Vec<f64> {
+    let n = pattern_spectrum.len();
+    let mut buffer = real_fft(signal, n);
+    for (b, p) in buffer.iter_mut().zip(pattern_spectrum) {
+        *b *= p.conj();
+    }
+    fft(&mut buffer, true);
+    buffer.iter().map(|c| c.re).collect()
+}
@@ -0,0 +1,333 @@
+use crate::pipeline::{map_rows, Stage};
+use ndarray::{Array1, Array2, ArrayView1};
+use std::f64::consts::PI;
+
+/* Per-trace digital filters used as pipeline stages. Cutoff frequencies are
+normalised to the sampling rate, i.e. in (0, 0.5). Samples outside the trace
+repeat the edge samples. */
+
+/// Windowed-sinc FIR filter with a Hamming window. The taps are symmetric
+/// and the output is centered, so the filter does not delay the trace.
+#[derive(Clone, Debug)]
+pub struct Fir {
+    taps: Vec<f64>,
+}
+
+impl Fir {
+    /// Low-pass filter of `len_taps` coefficients (rounded up to an odd number).
+    pub fn low_pass(cutoff: f64, len_taps: usize) -> Self {
+        let len_taps = len_taps | 1;
+        let center = (len_taps / 2) as f64;
+        let mut taps: Vec<f64> = (0..len_taps)
+            .map(|n| {
+                let x = n as f64 - center;
+                let sinc = if x == 0.0 {
+                    2.0 * cutoff
+                } else {
+                    (2.0 * PI * cutoff * x).sin() / (PI * x)
+                };
+                let window =
+                    0.54 - 0.46 * (2.0 * PI * n as f64 / (len_taps - 1).max(1) as f64).cos();
+                sinc * window
+            })
+            .collect();
+        let gain: f64 = taps.iter().sum();
+        taps.iter_mut().for_each(|t| *t /= gain);
+        Self { taps }
+    }
+
+    /// High-pass filter obtained by spectral inversion of the low-pass filter.
+    pub fn high_pass(cutoff: f64, len_taps: usize) -> Self {
+        let mut fir = Self::low_pass(cutoff, len_taps);
+        let center = fir.taps.len() / 2;
+        fir.taps.iter_mut().for_each(|t| *t = -*t);
+        fir.taps[center] += 1.0;
+        fir
+    }
+
+    /// Band-pass filter as the difference of two low-pass filters.
+    pub fn band_pass(low: f64, high: f64, len_taps: usize) -> Self {
+        let upper = Self::low_pass(high, len_taps);
+        let lower = Self::low_pass(low, len_taps);
+        let taps = upper
+            .taps
+            .iter()
+            .zip(&lower.taps)
+            .map(|(u, l)| u - l)
+            .collect();
+        Self { taps }
+    }
+
+    pub fn from_taps(taps: Vec<f64>) -> Self {
+        Self { taps }
+    }
+
+    pub fn taps(&self) -> &[f64] {
+        &self.taps
+    }
+
+    pub fn apply(&self, trace: ArrayView1<f32>) -> Array1<f32> {
+        let center = (self.taps.len() / 2) as isize;
+        let last = trace.len() as isize - 1;
+        Array1::from_shape_fn(trace.len(), |i| {
+            let mut acc = 0.0;
+            for (k, tap) in self.taps.iter().enumerate() {
+                let j = (i as isize + center - k as isize).clamp(0, last);
+                acc += tap * trace[j as usize] as f64;
+            }
+            acc as f32
+        })
+    }
+}
+
+impl Stage for Fir {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        let len_samples = traces.shape()[1];
+        (
+            map_rows(traces.view(), len_samples, |t| self.apply(t)),
+            metadata,
+        )
+    }
+}
+
+/// Second-order section of an IIR filter (direct form I), a0 = 1.
+#[derive(Clone, Copy, Debug)]
+pub struct Biquad {
+    pub b: [f64; 3],
+    pub a: [f64; 2],
+}
+
+impl Biquad {
+    fn filter(&self, x: &mut [f64]) {
+        let Some(first) = x.first() else {
+            return;
+        };
+        let (mut x1, mut x2, mut y1, mut y2) = (*first, *first, 0.0, 0.0);
+        /* Start in the steady state of the first sample to avoid a transient */
+        let dc = (self.b[0] + self.b[1] + self.b[2]) / (1.0 + self.a[0] + self.a[1]);
+        if dc.is_finite() {
+            y1 = dc * x1;
+            y2 = y1;
+        }
+        for sample in x.iter_mut() {
+            let y = self.b[0] * *sample + self.b[1] * x1 + self.b[2] * x2
+                - self.a[0] * y1
+                - self.a[1] * y2;
+            x2 = x1;
+            x1 = *sample;
+            y2 = y1;
+            y1 = y;
+            *sample = y;
+        }
+    }
+}
+
+/// Butterworth IIR filter as a cascade of biquads, designed with the bilinear
+/// transform. With `zero_phase` the cascade is run forward then backward
+/// (doubling the attenuation) so that the leakage is not delayed.
+#[derive(Clone, Debug)]
+pub struct Iir {
+    sections: Vec<Biquad>,
+    zero_phase: bool,
+}
+
+impl Iir {
+    fn butterworth(order: usize, cutoff: f64, high: bool) -> Vec<Biquad> {
+        let w0 = 2.0 * PI * cutoff;
+        let (sin, cos) = w0.sin_cos();
+        let mut sections = Vec::new();
+        for k in 0..order / 2 {
+            let q = 1.0 / (2.0 * (PI * (2 * k + 1) as f64 / (2 * order) as f64).sin());
+            let alpha = sin / (2.0 * q);
+            let a0 = 1.0 + alpha;
+            let b = if high {
+                [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0]
+            } else {
+                [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0]
+            };
+            sections.push(Biquad {
+                b: b.map(|x| x / a0),
+                a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            });
+        }
+        if order % 2 == 1 {
+            /* First-order section for odd orders */
+            let k = (w0 / 2.0).tan();
+            let b = if high {
+                [1.0 / (1.0 + k), -1.0 / (1.0 + k), 0.0]
+            } else {
+                [k / (1.0 + k), k / (1.0 + k), 0.0]
+            };
+            sections.push(Biquad {
+                b,
+                a: [(k - 1.0) / (k + 1.0), 0.0],
+            });
+        }
+        sections
+    }
+
+    pub fn butterworth_low_pass(order: usize, cutoff: f64) -> Self {
+        Self::from_sections(Self::butterworth(order, cutoff, false))
+    }
+
+    pub fn butterworth_high_pass(order: usize, cutoff: f64) -> Self {
+        Self::from_sections(Self::butterworth(order, cutoff, true))
+    }
+
+    /// Band-pass filter as a high-pass at `low` followed by a low-pass at `high`.
+    pub fn butterworth_band_pass(order: usize, low: f64, high: f64) -> Self {
+        let mut sections = Self::butterworth(order, low, true);
+        sections.extend(Self::butterworth(order, high, false));
+        Self::from_sections(sections)
+    }
+
+    pub fn from_sections(sections: Vec<Biquad>) -> Self {
+        Self {
+            sections,
+            zero_phase: false,
+        }
+    }
+
+    pub fn zero_phase(&mut self, zero_phase: bool) {
+        self.zero_phase = zero_phase;
+    }
+
+    pub fn sections(&self) -> &[Biquad] {
+        &self.sections
+    }
+
+    pub fn apply(&self, trace: ArrayView1<f32>) -> Array1<f32> {
+        let mut x: Vec<f64> = trace.iter().map(|t| *t as f64).collect();
+        for section in self.sections.iter() {
+            section.filter(&mut x);
+        }
+        if self.zero_phase {
+            x.reverse();
+            for section in self.sections.iter() {
+                section.filter(&mut x);
+            }
+            x.reverse();
+        }
+        x.into_iter().map(|t| t as f32).collect()
+    }
+}
+
+impl Stage for Iir {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        let len_samples = traces.shape()[1];
+        (
+            map_rows(traces.view(), len_samples, |t| self.apply(t)),
+            metadata,
+        )
+    }
+}
+
+/// Centered moving average over `window` samples.
+#[derive(Clone, Copy, Debug)]
+pub struct MovingAverage {
+    window: usize,
+}
+
+impl MovingAverage {
+    pub fn new(window: usize) -> Self {
+        assert!(window > 0, "the moving average window must not be empty");
+        Self { window }
+    }
+
+    pub fn apply(&self, trace: ArrayView1<f32>) -> Array1<f32> {
+        moving_average(trace, self.window)
+    }
+}
+
+impl Stage for MovingAverage {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        let len_samples = traces.shape()[1];
+        (
+            map_rows(traces.view(), len_samples, |t| self.apply(t)),
+            metadata,
+        )
+    }
+}
+
+fn moving_average(trace: ArrayView1<f32>, window: usize) -> Array1<f32> {
+    assert!(window > 0, "the moving average window must not be empty");
+    let len = trace.len();
+    let mut sum = vec![0.0f64; len + 1];
+    for i in 0..len {
+        sum[i + 1] = sum[i] + trace[i] as f64;
+    }
+    let before = (window - 1) / 2;
+    Array1::from_shape_fn(len, |i| {
+        let start = i.saturating_sub(before);
+        let end = (i + window - before).min(len);
+        ((sum[end] - sum[start]) / (end - start) as f64) as f32
+    })
+}
+
+/// Removal of the DC offset or of a slowly varying baseline.
+#[derive(Clone, Copy, Debug)]
+pub enum DcRemoval {
+    /// Subtracts the mean of the whole trace.
+    Mean,
+    /// Subtracts the mean of the samples start..end, e.g. a quiet window
+    /// before the operation. The window must not be empty.
+    Window(usize, usize),
+    /// Subtracts a centered moving average of the given length (> 0).
+    Moving(usize),
+}
+
+impl DcRemoval {
+    pub fn apply(&self, trace: ArrayView1<f32>) -> Array1<f32> {
+        match *self {
+            DcRemoval::Mean => {
+                let mean = trace.iter().map(|t| *t as f64).sum::<f64>() / trace.len() as f64;
+                trace.map(|t| (*t as f64 - mean) as f32)
+            }
+            DcRemoval::Window(start, end) => {
+                assert!(start < end, "the DC window {start}..{end} is empty");
+                let mean =
+                    (start..end).map(|i| trace[i] as f64).sum::<f64>() / (end - start) as f64;
+                trace.map(|t| (*t as f64 - mean) as f32)
+            }
+            DcRemoval::Moving(window) => &trace - &moving_average(trace, window),
+        }
+    }
+}
+
+impl Stage for DcRemoval {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        let len_samples = traces.shape()[1];
+        (
+            map_rows(traces.view(), len_samples, |t| self.apply(t)),
+            metadata,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_trace() {
+        let mut iir = Iir::butterworth_band_pass(3, 0.05, 0.2);
+        iir.zero_phase(true);
+        assert!(iir.apply(Array1::zeros(0).view()).is_empty());
+    }
+}
@@ -0,0 +1,319 @@
+/* GIFT-64/128 primitives used to build leakage models, as specified in:
+https://eprint.iacr.org/2017/622.pdf
+The key state is stored as k7||k6||...||k0 with k7 in the most significant
+16 bits. Round keys are packed as U||V. The first round key is only added
+after SubCells and PermBits, so a CPA targets the sbox of the second round,
+where each nibble depends on two bits of the round key (guess_range 4). */
+
+pub const SBOX: [u8; 16] = [
+    0x1, 0xA, 0x4, 0xC, 0x6, 0xF, 0x3, 0x9, 0x2, 0xD, 0xB, 0x7, 0x5, 0x0, 0x8, 0xE,
+];
+
+pub const INV_SBOX: [u8; 16] = [
+    0xD, 0x0, 0x8, 0x6, 0x2, 0xC, 0x4, 0xB, 0xE, 0x7, 0x1, 0xA, 0x3, 0x9, 0xF, 0x5,
+];
+
+pub const ROUNDS_64: usize = 28;
+pub const ROUNDS_128: usize = 40;
+
+pub fn sbox(index: u8) -> u8 {
+    SBOX[(index & 0xF) as usize]
+}
+
+pub fn inv_sbox(index: u8) -> u8 {
+    INV_SBOX[(index & 0xF) as usize]
+}
+
+/// Round constant of round `round` (starting at 1).
+pub fn round_constant(round: usize) -> u8 {
+    let mut constant: u8 = 0;
+    for _ in 0..round {
+        let feedback = ((constant >> 5) ^ (constant >> 4) ^ 1) & 1;
+        constant = ((constant << 1) | feedback) & 0x3F;
+    }
+    constant
+}
+
+fn sub_cells(state: u128, nibbles: usize) -> u128 {
+    (0..nibbles).fold(0, |acc, i| {
+        acc | ((sbox(((state >> (4 * i)) & 0xF) as u8) as u128) << (4 * i))
+    })
+}
+
+fn perm_bits(state: u128, bits: usize) -> u128 {
+    let mut out: u128 = 0;
+    for i in 0..bits {
+        let target = 4 * (i / 16) + (bits / 4) * ((3 * ((i % 16) / 4) + (i % 4)) % 4) + (i % 4);
+        out |= ((state >> i) & 1) << target;
+    }
+    out
+}
+
+fn add_constant(state: u128, round: usize, bits: usize) -> u128 {
+    let constant = round_constant(round);
+    let mut out = state ^ (1 << (bits - 1));
+    for i in 0..6 {
+        out ^= (((constant >> i) & 1) as u128) << (4 * i + 3);
+    }
+    out
+}
+
+fn word(key_state: u128, index: usize) -> u128 {
+    (key_state >> (16 * index)) & 0xFFFF
+}
+
+fn update_key(key_state: u128) -> u128 {
+    let k1 = (word(key_state, 1) as u16).rotate_right(2) as u128;
+    let k0 = (word(key_state, 0) as u16).rotate_right(12) as u128;
+    (k1 << 112) | (k0 << 96) | (key_state >> 32)
+}
+
+fn inverse_update_key(key_state: u128) -> u128 {
+    let k1 = (word(key_state, 7) as u16).rotate_left(2) as u128;
+    let k0 = (word(key_state, 6) as u16).rotate_left(12) as u128;
+    (key_state << 32) | (k1 << 16) | k0
+}
+
+/// Sub cells of the 16 nibbles of a GIFT-64 state.
+pub fn sub_cells_64(state: u64) -> u64 {
+    sub_cells(state as u128, 16) as u64
+}
+
+pub fn perm_bits_64(state: u64) -> u64 {
+    perm_bits(state as u128, 64) as u64
+}
+
+/// Adds the round key U||V and the round constant of round `round`.
+pub fn add_round_key_64(state: u64, round_key: u32, round: usize) -> u64 {
+    let (u, v) = (round_key >> 16, round_key & 0xFFFF);
+    let mut out = state as u128;
+    for i in 0..16 {
+        out ^= (((u >> i) & 1) as u128) << (4 * i + 1);
+        out ^= (((v >> i) & 1) as u128) << (4 * i);
+    }
+    add_constant(out, round, 64) as u64
+}
+
+pub fn round_64(state: u64, round_key: u32, round: usize) -> u64 {
+    add_round_key_64(perm_bits_64(sub_cells_64(state)), round_key, round)
+}
+
+/// Key independent state after SubCells, PermBits and the constant of round 1.
+pub fn first_round_64(plaintext: u64) -> u64 {
+    add_round_key_64(perm_bits_64(sub_cells_64(plaintext)), 0, 1)
+}
+
+/// Places a 2-bit guess (v in bit 0, u in bit 1) in a GIFT-64 nibble.
+pub fn key_nibble_64(guess: usize) -> u8 {
+    (guess & 0x3) as u8
+}
+
+/// Combines the 16 2-bit nibble guesses into a GIFT-64 round key U||V.
+pub fn combine_guesses_64(guesses: &[i32]) -> u32 {
+    guesses.iter().enumerate().fold(0, |acc, (i, g)| {
+        acc | (((*g as u32 >> 1) & 1) << (16 + i)) | ((*g as u32 & 1) << i)
+    })
+}
+
+pub fn key_schedule_64(key: u128) -> Vec<u32> {
+    let mut key_state = key;
+    let mut round_keys = Vec::with_capacity(ROUNDS_64);
+    for _ in 0..ROUNDS_64 {
+        round_keys.push(((word(key_state, 1) << 16) | word(key_state, 0)) as u32);
+        key_state = update_key(key_state);
+    }
+    round_keys
+}
+
+pub fn encrypt_64(plaintext: u64, round_keys: &[u32]) -> u64 {
+    let mut state = plaintext;
+    for (round, round_key) in round_keys.iter().enumerate() {
+        state = round_64(state, *round_key, round + 1);
+    }
+    state
+}
+
+/// Recovers the key from the round keys of rounds `round` to `round + 3`.
+pub fn invert_key_schedule_64(round_keys: [u32; 4], round: usize) -> u128 {
+    let mut key_state = round_keys
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, rk)| acc | ((*rk as u128) << (32 * i)));
+    for _ in 1..round {
+        key_state = inverse_update_key(key_state);
+    }
+    key_state
+}
+
+/// Sub cells of the 32 nibbles of a GIFT-128 state.
+pub fn sub_cells_128(state: u128) -> u128 {
+    sub_cells(state, 32)
+}
+
+pub fn perm_bits_128(state: u128) -> u128 {
+    perm_bits(state, 128)
+}
+
+/// Adds the round key U||V and the round constant of round `round`.
+pub fn add_round_key_128(state: u128, round_key: u64, round: usize) -> u128 {
+    let (u, v) = (round_key >> 32, round_key & 0xFFFF_FFFF);
+    let mut out = state;
+    for i in 0..32 {
+        out ^= (((u >> i) & 1) as u128) << (4 * i + 2);
+        out ^= (((v >> i) & 1) as u128) << (4 * i + 1);
+    }
+    add_constant(out, round, 128)
+}
+
+pub fn round_128(state: u128, round_key: u64, round: usize) -> u128 {
+    add_round_key_128(perm_bits_128(sub_cells_128(state)), round_key, round)
+}
+
+/// Key independent state after SubCells, PermBits and the constant of round 1.
+pub fn first_round_128(plaintext: u128) -> u128 {
+    add_round_key_128(perm_bits_128(sub_cells_128(plaintext)), 0, 1)
+}
+
+/// Places a 2-bit guess (v in bit 1, u in bit 2) in a GIFT-128 nibble.
+pub fn key_nibble_128(guess: usize) -> u8 {
+    ((guess & 0x3) << 1) as u8
+}
+
+/// Combines the 32 2-bit nibble guesses into a GIFT-128 round key U||V.
+pub fn combine_guesses_128(guesses: &[i32]) -> u64 {
+    guesses.iter().enumerate().fold(0, |acc, (i, g)| {
+        acc | (((*g as u64 >> 1) & 1) << (32 + i)) | ((*g as u64 & 1) << i)
+    })
+}
+
+pub fn key_schedule_128(key: u128) -> Vec<u64> {
+    let mut key_state = key;
+    let mut round_keys = Vec::with_capacity(ROUNDS_128);
+    for _ in 0..ROUNDS_128 {
+        let u = (word(key_state, 5) << 16) | word(key_state, 4);
+        let v = (word(key_state, 1) << 16) | word(key_state, 0);
+        round_keys.push(((u << 32) | v) as u64);
+        key_state = update_key(key_state);
+    }
+    round_keys
+}
+
+pub fn encrypt_128(plaintext: u128, round_keys: &[u64]) -> u128 {
+    let mut state = plaintext;
+    for (round, round_key) in round_keys.iter().enumerate() {
+        state = round_128(state, *round_key, round + 1);
+    }
+    state
+}
+
+/// Recovers the key from the round keys of rounds `round` and `round + 1`.
+pub fn invert_key_schedule_128(round_keys: [u64; 2], round: usize) -> u128 {
+    let [first, second] = round_keys.map(|rk| rk as u128);
+    let mut key_state = ((second >> 32) << 96)
+        | ((first >> 32) << 64)
+        | ((second & 0xFFFF_FFFF) << 32)
+        | (first & 0xFFFF_FFFF);
+    for _ in 1..round {
+        key_state = inverse_update_key(key_state);
+    }
+    key_state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_answers_64() {
+        for (plaintext, key, ciphertext) in [
+            (0, 0, 0xF62B_C3EF_34F7_75AC),
+            (
+                0xFEDC_BA98_7654_3210,
+                0xFEDC_BA98_7654_3210_FEDC_BA98_7654_3210,
+                0xC1B7_1F66_160F_F587,
+            ),
+            (
+                0xC450_C772_7A9B_8A7D,
+                0xBD91_731E_B6BC_2713_A1F9_F6FF_C750_44E7,
+                0xE327_2885_FA94_BA8B,
+            ),
+        ] {
+            assert_eq!(encrypt_64(plaintext, &key_schedule_64(key)), ciphertext);
+        }
+    }
+
+    #[test]
+    fn known_answers_128() {
+        for (plaintext, key, ciphertext) in [
+            (0, 0, 0xCD0B_D738_388A_D3F6_68B1_5A36_CEB6_FF92),
+            (
+                0xFEDC_BA98_7654_3210_FEDC_BA98_7654_3210,
+                0xFEDC_BA98_7654_3210_FEDC_BA98_7654_3210,
+                0x8422_241A_6DBF_5A93_46AF_4684_09EE_0152,
+            ),
+            (
+                0xE39C_141F_A57D_BA43_F08A_85B6_A91F_86C1,
+                0xD0F5_C59A_7700_D3E7_9902_8FA9_F90A_D837,
+                0x13ED_E67C_BDCC_3DBF_400A_62D6_9772_65EA,
+            ),
+        ] {
+            assert_eq!(encrypt_128(plaintext, &key_schedule_128(key)), ciphertext);
+        }
+    }
+
+    #[test]
+    fn round_constants() {
+        /* First constants of the 6-bit LFSR listed in the specification */
+        let constants: Vec<u8> = (1..=8).map(round_constant).collect();
+        assert_eq!(constants, [0x01, 0x03, 0x07, 0x0F, 0x1F, 0x3E, 0x3D, 0x3B]);
+        for i in 0..16 {
+            assert_eq!(inv_sbox(sbox(i)), i);
+        }
+    }
+
+    /// The state entering the second round sbox is the key independent first
+    /// round xored with the 2-bit key nibbles, which is what a CPA guesses.
+    #[test]
+    fn second_round_input() {
+        let key = 0xBD91_731E_B6BC_2713_A1F9_F6FF_C750_44E7;
+        let plaintext = 0xC450_C772_7A9B_8A7D;
+        let round_key = key_schedule_64(key)[0];
+        let guesses: Vec<i32> = (0..16)
+            .map(|i| (((round_key >> (16 + i)) & 1) << 1 | ((round_key >> i) & 1)) as i32)
+            .collect();
+        assert_eq!(combine_guesses_64(&guesses), round_key);
+        let state = round_64(plaintext, round_key, 1);
+        let keys = guesses.iter().enumerate().fold(0, |acc, (i, g)| {
+            acc | (key_nibble_64(*g as usize) as u64) << (4 * i)
+        });
+        assert_eq!(state, first_round_64(plaintext) ^ keys);
+
+        let key = 0xD0F5_C59A_7700_D3E7_9902_8FA9_F90A_D837;
+        let plaintext = 0xE39C_141F_A57D_BA43_F08A_85B6_A91F_86C1;
+        let round_key = key_schedule_128(key)[0];
+        let guesses: Vec<i32> = (0..32)
+            .map(|i| (((round_key >> (32 + i)) & 1) << 1 | ((round_key >> i) & 1)) as i32)
+            .collect();
+        assert_eq!(combine_guesses_128(&guesses), round_key);
+        let state = round_128(plaintext, round_key, 1);
+        let keys = guesses.iter().enumerate().fold(0, |acc, (i, g)| {
+            acc | (key_nibble_128(*g as usize) as u128) << (4 * i)
+        });
+        assert_eq!(state, first_round_128(plaintext) ^ keys);
+    }
+
+    #[test]
+    fn key_schedule_inversion() {
+        let key = 0xBD91_731E_B6BC_2713_A1F9_F6FF_C750_44E7;
+        let round_keys = key_schedule_64(key);
+        for round in [1, 2, 10, ROUNDS_64 - 3] {
+            let keys = std::array::from_fn(|i| round_keys[round - 1 + i]);
+            assert_eq!(invert_key_schedule_64(keys, round), key);
+        }
+        let round_keys = key_schedule_128(key);
+        for round in [1, 2, 10, ROUNDS_128 - 1] {
+            let keys = [round_keys[round - 1], round_keys[round]];
+            assert_eq!(invert_key_schedule_128(keys, round), key);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use ndarray::ArrayView1;
+
 pub const SBOX: [u8; 256] = [
     0x63, 0x7C, 0x77, 0x7B, 0xF2, 0x6B, 0x6F, 0xC5, 0x30, 0x01, 0x67, 0x2B, 0xFE, 0xD7, 0xAB, 0x76,
     0xCA, 0x82, 0xC9, 0x7D, 0xFA, 0x59, 0x47, 0xF0, 0xAD, 0xD4, 0xA2, 0xAF, 0x9C, 0xA4, 0x72, 0xC0,
@@ -28,7 +30,26 @@ pub fn hw(value: usize) -> usize {
         if (tmp & 1) == 1 {
             n_ones += 1;
         }
-        tmp = tmp >> 1;
+        tmp >>= 1;
     }
     n_ones
 }
+
+/// Packs a metadata row of bytes (most significant byte first) into a word.
+pub fn to_word(row: ArrayView1<usize>) -> u128 {
+    row.iter()
+        .fold(0, |acc, b| (acc << 8) | (*b as u128 & 0xFF))
+}
+
+/// Returns the `index`-th nibble of a word, nibble 0 being the least significant.
+pub fn nibble(word: u128, index: usize) -> usize {
+    ((word >> (4 * index)) & 0xF) as usize
+}
+
+/// Combines per-nibble key guesses (nibble 0 first) into a round key.
+pub fn combine_nibbles(guesses: &[i32]) -> u128 {
+    guesses
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, g)| acc | ((*g as u128 & 0xF) << (4 * i)))
+}
@@ -1,5 +1,33 @@
+pub mod align;
+#[cfg(feature = "hdf5")]
+pub mod ascad;
+pub mod chipwhisperer;
+pub mod compress;
+pub mod convergence;
+pub mod convert;
 pub mod cpa_normal;
 pub mod cpa_partition;
 pub mod cpa_single;
+pub mod cpa_tiled;
+pub mod dataset;
+pub mod export;
+pub mod fft;
+pub mod filter;
+pub mod gift;
 pub mod leakage;
+pub mod metadata;
+pub mod npy;
+pub mod pipeline;
+pub mod poi;
+pub mod pqc;
+pub mod present;
+pub mod rank;
+pub mod reject;
+pub mod result;
+pub mod scope;
+pub mod skinny;
+pub mod slots;
+pub mod spectrum;
+pub mod stats;
 pub mod tools;
+pub mod trs;
@@ -0,0 +1,415 @@
+use crate::dataset::MetadataSource;
+use ndarray::{concatenate, s, Array2, Axis};
+use ndarray_npy::write_npy;
+use std::collections::hash_map::{Entry, HashMap};
+use std::io::{Error, ErrorKind, Result};
+
+/* Metadata logged as text by acquisition harnesses: one CSV line per trace
+with byte strings (plaintext, ciphertext, key) in hexadecimal, e.g.
+"00112233..." or "0x00 0x11 ...", or in decimal, e.g. "0 17 34 ...". A file
+with one hex string per line is a CSV file with a single column. Rows are
+matched with traces by line order, or by an index column when the harness
+logs one. Malformed lines do not stop the import: they are reported and
+their trace is listed as missing, so the other rows stay aligned with the
+trace files. The number of traces is the number of lines unless the caller
+gives it, the indices must be below it. The width of every field is the
+most common one among the lines. */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteFormat {
+    Hex,
+    Decimal,
+}
+
+impl ByteFormat {
+    fn parse(&self, text: &str) -> std::result::Result<Vec<u8>, String> {
+        /* '-' only separates hex bytes, so that "-1" is not read as 1 */
+        let tokens = text
+            .split(|c: char| {
+                c.is_whitespace() || c == ':' || c == ';' || (c == '-' && *self == ByteFormat::Hex)
+            })
+            .filter(|t| !t.is_empty());
+        match self {
+            ByteFormat::Hex => {
+                let mut digits = String::new();
+                for token in tokens {
+                    let token = token
+                        .strip_prefix("0x")
+                        .or_else(|| token.strip_prefix("0X"))
+                        .unwrap_or(token);
+                    if token.len() % 2 != 0 {
+                        return Err(format!("odd number of hex digits in {token:?}"));
+                    }
+                    digits.push_str(token);
+                }
+                (0..digits.len())
+                    .step_by(2)
+                    .map(|i| {
+                        digits
+                            .get(i..i + 2)
+                            .and_then(|d| u8::from_str_radix(d, 16).ok())
+                            .ok_or_else(|| format!("invalid hex string {text:?}"))
+                    })
+                    .collect()
+            }
+            ByteFormat::Decimal => tokens
+                .map(|t| t.parse().map_err(|_| format!("invalid byte {t:?}")))
+                .collect(),
+        }
+    }
+}
+
+/// CSV column, by position or by name in the header line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Malformed {
+    /// Line number in the file, starting at 1.
+    pub line: usize,
+    pub message: String,
+}
+
+pub struct MetadataCsv {
+    format: ByteFormat,
+    delimiter: u8,
+    header: bool,
+    index: Option<Column>,
+    /// Plaintext, ciphertext and key columns.
+    fields: [Option<Column>; 3],
+    len_traces: Option<usize>,
+}
+
+impl MetadataCsv {
+    /// Reader of comma separated byte strings without a header line, the
+    /// plaintext being in the first column.
+    pub fn new(format: ByteFormat) -> Self {
+        Self {
+            format,
+            delimiter: b',',
+            header: false,
+            index: None,
+            fields: [Some(Column::Index(0)), None, None],
+            len_traces: None,
+        }
+    }
+
+    pub fn delimiter(&mut self, delimiter: u8) {
+        self.delimiter = delimiter;
+    }
+
+    /// Whether the first line holds the column names.
+    pub fn header(&mut self, header: bool) {
+        self.header = header;
+    }
+
+    /// Column holding the trace index of every line, in decimal.
+    pub fn index(&mut self, column: Column) {
+        self.index = Some(column);
+    }
+
+    /// Number of traces of the campaign, instead of the number of lines.
+    /// Lines beyond it, or whose index is not below it, are malformed.
+    pub fn traces(&mut self, len: usize) {
+        self.len_traces = Some(len);
+    }
+
+    pub fn plaintext(&mut self, column: Option<Column>) {
+        self.fields[0] = column;
+    }
+
+    pub fn ciphertext(&mut self, column: Option<Column>) {
+        self.fields[1] = column;
+    }
+
+    pub fn key(&mut self, column: Option<Column>) {
+        self.fields[2] = column;
+    }
+
+    pub fn read(&self, path: &str) -> Result<Metadata> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.header)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let names = if self.header {
+            Some(reader.headers()?.clone())
+        } else {
+            None
+        };
+        let position = |column: &Column| match column {
+            Column::Index(i) => Ok(*i),
+            Column::Name(name) => names
+                .as_ref()
+                .and_then(|n| n.iter().position(|h| h == name))
+                .ok_or_else(|| invalid(format!("no column {name} in {path}"))),
+        };
+        let index = self.index.as_ref().map(position).transpose()?;
+        let fields = self
+            .fields
+            .iter()
+            .map(|f| f.as_ref().map(position).transpose())
+            .collect::<Result<Vec<Option<usize>>>>()?;
+
+        let records = reader.records().collect::<csv::Result<Vec<_>>>()?;
+        let len_traces = self.len_traces.unwrap_or(records.len());
+        let mut parsed: Vec<(usize, usize, Vec<Vec<u8>>)> = Vec::new();
+        let mut malformed = Vec::new();
+        for (i, record) in records.iter().enumerate() {
+            let line = record.position().map_or(0, |p| p.line() as usize);
+            let trace = match index {
+                None => Ok(i),
+                Some(column) => record
+                    .get(column)
+                    .and_then(|t| t.parse::<usize>().ok())
+                    .ok_or_else(|| "missing or invalid trace index".to_string()),
+            };
+            let trace = trace.and_then(|trace| {
+                if trace < len_traces {
+                    Ok(trace)
+                } else {
+                    Err(format!(
+                        "trace index {trace} is out of range for {len_traces} traces"
+                    ))
+                }
+            });
+            let values = trace.and_then(|trace| {
+                fields
+                    .iter()
+                    .map(|field| {
+                        let Some(column) = field else {
+                            return Ok(Vec::new());
+                        };
+                        let text = record
+                            .get(*column)
+                            .ok_or_else(|| format!("no column {column}"))?;
+                        self.format.parse(text)
+                    })
+                    .collect::<std::result::Result<Vec<Vec<u8>>, String>>()
+                    .map(|values| (line, trace, values))
+            });
+            match values {
+                Ok(values) => parsed.push(values),
+                Err(message) => malformed.push(Malformed { line, message }),
+            }
+        }
+
+        /* The most common width of every field, so that a malformed first
+        line does not reject the others */
+        let widths: Vec<usize> = (0..fields.len())
+            .map(|f| most_common(parsed.iter().map(|(_, _, values)| values[f].len())))
+            .collect();
+        let mut rows: HashMap<usize, Vec<Vec<u8>>> = HashMap::new();
+        for (line, trace, values) in parsed {
+            let width = values.iter().zip(&widths).find(|(v, w)| v.len() != **w);
+            if let Some((value, width)) = width {
+                malformed.push(Malformed {
+                    line,
+                    message: format!("{} bytes instead of {width}", value.len()),
+                });
+            } else {
+                match rows.entry(trace) {
+                    Entry::Occupied(_) => malformed.push(Malformed {
+                        line,
+                        message: format!("duplicate trace index {trace}"),
+                    }),
+                    Entry::Vacant(entry) => {
+                        entry.insert(values);
+                    }
+                }
+            }
+        }
+        malformed.sort_by_key(|m| m.line);
+
+        let mut arrays = widths.iter().map(|w| Array2::zeros((len_traces, *w)));
+        let [mut plaintext, mut ciphertext, mut key]: [Array2<u8>; 3] =
+            std::array::from_fn(|_| arrays.next().unwrap());
+        for (trace, values) in &rows {
+            for (array, value) in [&mut plaintext, &mut ciphertext, &mut key]
+                .into_iter()
+                .zip(values)
+            {
+                array
+                    .row_mut(*trace)
+                    .iter_mut()
+                    .zip(value)
+                    .for_each(|(a, v)| *a = *v);
+            }
+        }
+        let missing = (0..len_traces).filter(|t| !rows.contains_key(t)).collect();
+        Ok(Metadata {
+            plaintext,
+            ciphertext,
+            key,
+            malformed,
+            missing,
+        })
+    }
+}
+
+/// Metadata rows, row i belonging to trace i. Fields that were not read have
+/// zero columns.
+pub struct Metadata {
+    pub plaintext: Array2<u8>,
+    pub ciphertext: Array2<u8>,
+    pub key: Array2<u8>,
+    malformed: Vec<Malformed>,
+    missing: Vec<usize>,
+}
+
+impl Metadata {
+    pub fn len_traces(&self) -> usize {
+        self.plaintext.nrows()
+    }
+
+    /// Lines that could not be parsed.
+    pub fn malformed(&self) -> &[Malformed] {
+        &self.malformed
+    }
+
+    /// Traces without a valid line, their rows being zero.
+    pub fn missing(&self) -> &[usize] {
+        &self.missing
+    }
+
+    /// Rows start..start + len (clipped) of the plaintext, ciphertext and key
+    /// side by side, like the metadata NPY files.
+    pub fn batch(&self, start: usize, len: usize) -> Array2<u8> {
+        let end = (start + len).min(self.len_traces());
+        let rows = s![start.min(end)..end, ..];
+        concatenate(
+            Axis(1),
+            &[
+                self.plaintext.slice(rows),
+                self.ciphertext.slice(rows),
+                self.key.slice(rows),
+            ],
+        )
+        .unwrap()
+    }
+
+    /// Batches of `len` rows in order.
+    pub fn batches(&self, len: usize) -> impl Iterator<Item = Array2<u8>> + '_ {
+        (0..self.len_traces())
+            .step_by(len)
+            .map(move |start| self.batch(start, len))
+    }
+
+    /// Writes the rows matching trace files of `lens` traces each to the NPY
+    /// files of `pattern`, where `{i}` stands for the file number, e.g.
+    /// "p/{i}.npy". The rows must cover the traces exactly.
+    pub fn write_npy_files(&self, pattern: &str, lens: &[usize]) -> Result<()> {
+        if lens.iter().sum::<usize>() != self.len_traces() {
+            return Err(invalid(format!(
+                "{} metadata rows for {} traces",
+                self.len_traces(),
+                lens.iter().sum::<usize>()
+            )));
+        }
+        let mut start = 0;
+        for (i, len) in lens.iter().enumerate() {
+            let path = pattern.replace("{i}", &i.to_string());
+            write_npy(&path, &self.batch(start, *len)).map_err(Error::other)?;
+            start += len;
+        }
+        Ok(())
+    }
+}
+
+impl MetadataSource for Metadata {
+    fn len_traces(&self) -> usize {
+        Metadata::len_traces(self)
+    }
+
+    fn metadata(&self, start: usize, len: usize) -> Result<Array2<u8>> {
+        Ok(Metadata::batch(self, start, len))
+    }
+}
+
+/// Most common value, the first one seen among equally common values, 0
+/// when there is none.
+fn most_common(values: impl Iterator<Item = usize>) -> usize {
+    let mut counts: Vec<(usize, usize)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+    /* max_by_key returns the last maximum */
+    counts
+        .iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map_or(0, |(value, _)| *value)
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    fn read(csv: &MetadataCsv, name: &str, text: &str) -> Metadata {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, text).unwrap();
+        let metadata = csv.read(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        metadata
+    }
+
+    #[test]
+    fn line_order() {
+        let csv = MetadataCsv::new(ByteFormat::Hex);
+        let metadata = read(&csv, "cpa_line_order.csv", "0011\n2233\nzz\n");
+        assert_eq!(metadata.len_traces(), 3);
+        assert_eq!(
+            metadata.plaintext,
+            array![[0x00, 0x11], [0x22, 0x33], [0, 0]]
+        );
+        assert_eq!(metadata.missing(), &[2]);
+        assert_eq!(metadata.malformed()[0].line, 3);
+    }
+
+    #[test]
+    fn expected_traces() {
+        let mut csv = MetadataCsv::new(ByteFormat::Decimal);
+        csv.index(Column::Index(0));
+        csv.plaintext(Some(Column::Index(1)));
+        csv.traces(4);
+        let metadata = read(&csv, "cpa_expected.csv", "2,5 6\n0,1 2\n4,9 9\n");
+        assert_eq!(metadata.len_traces(), 4);
+        assert_eq!(metadata.plaintext, array![[1, 2], [0, 0], [5, 6], [0, 0]]);
+        assert_eq!(metadata.missing(), &[1, 3]);
+        let lines: Vec<usize> = metadata.malformed().iter().map(|m| m.line).collect();
+        assert_eq!(lines, vec![3]);
+
+        let mut csv = MetadataCsv::new(ByteFormat::Hex);
+        csv.traces(2);
+        let metadata = read(&csv, "cpa_expected_lines.csv", "00\n11\n22\n");
+        assert_eq!(metadata.plaintext, array![[0x00], [0x11]]);
+        assert_eq!(metadata.malformed()[0].line, 3);
+    }
+
+    #[test]
+    fn most_common_width() {
+        let csv = MetadataCsv::new(ByteFormat::Hex);
+        let metadata = read(
+            &csv,
+            "cpa_width.csv",
+            "0011\n223344\n556677\n8899\naabbcc\n",
+        );
+        assert_eq!(metadata.plaintext.ncols(), 3);
+        assert_eq!(metadata.missing(), &[0, 3]);
+        let lines: Vec<usize> = metadata.malformed().iter().map(|m| m.line).collect();
+        assert_eq!(lines, vec![1, 4]);
+        assert_eq!(metadata.plaintext.row(4), array![0xaa, 0xbb, 0xcc]);
+    }
+}
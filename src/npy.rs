@@ -0,0 +1,616 @@
+use memmap2::Mmap;
+use ndarray::{s, Array2, ArrayD, ArrayView2, Ix2, ShapeBuilder};
+use ndarray_npy::{ReadNpyExt, ReadableElement};
+use num_traits::{FromPrimitive, Zero};
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+/* Memory-mapped readers of 2-D NPY files (one trace per row). The file is
+never loaded as a whole: the operating system only pages in what the
+engines read. NpyReader returns batches of rows as views into the mapping,
+restricted to a window of samples, and requires the element type at compile
+time; Fortran-ordered or big-endian files cannot be viewed in place and are
+read into memory instead. NpySource finds the element type in the header at
+run time, accepts either byte order and memory layout, and converts every
+batch to the type requested by the caller. NpyWriter streams
+batches of f32 traces to a file of any of these types. */
+
+/// Element types that can be viewed directly in the mapping.
+pub trait NpyElement: Copy + ReadableElement {
+    /// Type code in the NPY `descr` field, without the byte order.
+    const DESCR: &'static str;
+}
+
+macro_rules! npy_element {
+    ($($t:ty => $descr:literal),*) => {
+        $(impl NpyElement for $t {
+            const DESCR: &'static str = $descr;
+        })*
+    };
+}
+
+npy_element!(u8 => "u1", i8 => "i1", u16 => "u2", i16 => "i2", u32 => "u4", i32 => "i4",
+    u64 => "u8", i64 => "i8", f32 => "f4", f64 => "f8");
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NpyHeader {
+    /// Type code without the byte order, e.g. "f4".
+    pub descr: String,
+    pub big_endian: bool,
+    pub fortran_order: bool,
+    pub shape: (usize, usize),
+    /// Offset of the data in the file.
+    pub data_offset: usize,
+}
+
+impl NpyHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 10 || &bytes[..6] != b"\x93NUMPY" {
+            return Err(invalid("not an NPY file"));
+        }
+        let (len, start) = match bytes[6] {
+            1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+            _ if bytes.len() >= 12 => (
+                u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+                12,
+            ),
+            _ => return Err(invalid("truncated NPY header")),
+        };
+        let dict = bytes
+            .get(start..start + len)
+            .ok_or_else(|| invalid("truncated NPY header"))?;
+        let dict = String::from_utf8_lossy(dict);
+        let fortran_order = field(&dict, "fortran_order")?.starts_with("True");
+        let descr = field(&dict, "descr")?;
+        let descr = descr.trim_start_matches(['\'', '"']);
+        let descr = descr
+            .split(['\'', '"'])
+            .next()
+            .ok_or_else(|| invalid("invalid descr"))?;
+        let (descr, big_endian) = match descr.as_bytes().first() {
+            Some(b'<') | Some(b'|') | Some(b'=') => (&descr[1..], false),
+            Some(b'>') => (&descr[1..], true),
+            _ => (descr, false),
+        };
+        let shape = field(&dict, "shape")?;
+        let shape = &shape[1..shape.find(')').ok_or_else(|| invalid("invalid shape"))?];
+        let dims = shape
+            .split(',')
+            .map(|d| d.trim())
+            .filter(|d| !d.is_empty())
+            .map(|d| d.parse::<usize>().map_err(|_| invalid("invalid shape")))
+            .collect::<Result<Vec<usize>>>()?;
+        let shape = match dims[..] {
+            [rows, cols] => (rows, cols),
+            [cols] => (1, cols),
+            _ => return Err(invalid("NPY file is not 1-D or 2-D")),
+        };
+        Ok(Self {
+            descr: descr.to_string(),
+            big_endian,
+            fortran_order,
+            shape,
+            data_offset: start + len,
+        })
+    }
+
+    /// End of the data in the file for elements of `size` bytes, None when
+    /// it overflows.
+    fn data_end(&self, size: usize) -> Option<usize> {
+        let (rows, cols) = self.shape;
+        rows.checked_mul(cols)?
+            .checked_mul(size)?
+            .checked_add(self.data_offset)
+    }
+}
+
+/// Value of `key` in the header dictionary, up to the end of the dictionary.
+fn field<'a>(dict: &'a str, key: &str) -> Result<&'a str> {
+    let pos = dict
+        .find(&format!("'{key}'"))
+        .ok_or_else(|| invalid(&format!("missing {key} in NPY header")))?;
+    let rest = &dict[pos + key.len() + 2..];
+    Ok(rest.trim_start_matches([':', ' ']))
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// Elements of an NpyReader: the mapping, or a copy of the file when it
+/// cannot be viewed in place.
+enum Storage<T> {
+    Mapped(Mmap),
+    Copied(Array2<T>),
+}
+
+pub struct NpyReader<T: NpyElement> {
+    storage: Storage<T>,
+    header: NpyHeader,
+    window: Range<usize>,
+}
+
+impl<T: NpyElement> NpyReader<T> {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path)?;
+        /* Safety: the file must not be modified while it is mapped */
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header = NpyHeader::parse(&mmap)?;
+        if header.descr != T::DESCR {
+            return Err(invalid(&format!(
+                "{path} holds {} values, not {}",
+                header.descr,
+                T::DESCR
+            )));
+        }
+        let (rows, cols) = header.shape;
+        match header.data_end(std::mem::size_of::<T>()) {
+            Some(end) if end <= mmap.len() => {}
+            _ => return Err(invalid(&format!("{path} is truncated"))),
+        }
+        let storage = if header.big_endian != cfg!(target_endian = "big") || header.fortran_order {
+            let file = File::open(path)?;
+            let array = ArrayD::<T>::read_npy(file)
+                .map_err(|e| invalid(&format!("{path}: {e}")))?
+                .into_shape((rows, cols))
+                .map_err(|e| invalid(&format!("{path}: {e}")))?;
+            Storage::Copied(array.into_dimensionality::<Ix2>().unwrap())
+        } else {
+            let address = mmap.as_ptr() as usize + header.data_offset;
+            if !address.is_multiple_of(std::mem::align_of::<T>()) {
+                return Err(invalid(&format!(
+                    "the data of {path} is not aligned for {} values",
+                    T::DESCR
+                )));
+            }
+            Storage::Mapped(mmap)
+        };
+        Ok(Self {
+            window: 0..cols,
+            storage,
+            header,
+        })
+    }
+
+    /// Restricts the returned traces to the samples start..end.
+    pub fn window(&mut self, start: usize, end: usize) {
+        assert!(start <= end && end <= self.header.shape.1);
+        self.window = start..end;
+    }
+
+    pub fn header(&self) -> &NpyHeader {
+        &self.header
+    }
+
+    pub fn len_traces(&self) -> usize {
+        self.header.shape.0
+    }
+
+    /// Number of samples in the window.
+    pub fn len_samples(&self) -> usize {
+        self.window.len()
+    }
+
+    /// View of all the traces, restricted to the window.
+    pub fn view(&self) -> ArrayView2<'_, T> {
+        let (rows, cols) = self.header.shape;
+        let all = match &self.storage {
+            Storage::Mapped(mmap) => {
+                let bytes = &mmap[self.header.data_offset..];
+                /* Safety: T is a plain numeric type valid for any bit pattern,
+                open checked the alignment */
+                let (_, data, _) = unsafe { bytes.align_to::<T>() };
+                ArrayView2::from_shape((rows, cols).strides((cols, 1)), &data[..rows * cols])
+                    .unwrap()
+            }
+            Storage::Copied(array) => array.view(),
+        };
+        all.slice_move(s![.., self.window.clone()])
+    }
+
+    /// Traces start..start + len (clipped to the file), restricted to the window.
+    pub fn chunk(&self, start: usize, len: usize) -> ArrayView2<'_, T> {
+        let end = (start + len).min(self.len_traces());
+        self.view().slice_move(s![start.min(end)..end, ..])
+    }
+
+    /// Iterates over batches of `len` traces.
+    pub fn chunks(&self, len: usize) -> impl Iterator<Item = ArrayView2<'_, T>> {
+        (0..self.len_traces())
+            .step_by(len)
+            .map(move |start| self.chunk(start, len))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dtype {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Dtype {
+    pub fn from_descr(descr: &str) -> Option<Self> {
+        match descr {
+            "i1" => Some(Dtype::I8),
+            "u1" | "b1" => Some(Dtype::U8),
+            "i2" => Some(Dtype::I16),
+            "u2" => Some(Dtype::U16),
+            "i4" => Some(Dtype::I32),
+            "u4" => Some(Dtype::U32),
+            "f4" => Some(Dtype::F32),
+            "f8" => Some(Dtype::F64),
+            _ => None,
+        }
+    }
+
+    /// Type code in the NPY `descr` field, without the byte order.
+    pub fn descr(&self) -> &'static str {
+        match self {
+            Dtype::I8 => "i1",
+            Dtype::U8 => "u1",
+            Dtype::I16 => "i2",
+            Dtype::U16 => "u2",
+            Dtype::I32 => "i4",
+            Dtype::U32 => "u4",
+            Dtype::F32 => "f4",
+            Dtype::F64 => "f8",
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Dtype::I8 | Dtype::U8 => 1,
+            Dtype::I16 | Dtype::U16 => 2,
+            Dtype::I32 | Dtype::U32 | Dtype::F32 => 4,
+            Dtype::F64 => 8,
+        }
+    }
+
+    /// Decodes one element, every supported type being exact in f64.
+    pub(crate) fn decode(&self, bytes: &[u8], big_endian: bool) -> f64 {
+        macro_rules! decode {
+            ($t:ty, $n:literal) => {{
+                let raw: [u8; $n] = bytes[..$n].try_into().unwrap();
+                if big_endian {
+                    <$t>::from_be_bytes(raw) as f64
+                } else {
+                    <$t>::from_le_bytes(raw) as f64
+                }
+            }};
+        }
+        match self {
+            Dtype::I8 => bytes[0] as i8 as f64,
+            Dtype::U8 => bytes[0] as f64,
+            Dtype::I16 => decode!(i16, 2),
+            Dtype::U16 => decode!(u16, 2),
+            Dtype::I32 => decode!(i32, 4),
+            Dtype::U32 => decode!(u32, 4),
+            Dtype::F32 => decode!(f32, 4),
+            Dtype::F64 => decode!(f64, 8),
+        }
+    }
+
+    /// Encodes `value` in little-endian. NPY files have no scale, so the
+    /// integer types only take integer values within their range.
+    pub(crate) fn encode(&self, value: f64, out: &mut Vec<u8>) -> Result<()> {
+        macro_rules! encode_int {
+            ($t:ty) => {{
+                if value.fract() != 0.0 || value < <$t>::MIN as f64 || value > <$t>::MAX as f64 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{value} does not fit in {}", stringify!($t)),
+                    ));
+                }
+                out.extend((value as $t).to_le_bytes())
+            }};
+        }
+        match self {
+            Dtype::I8 => encode_int!(i8),
+            Dtype::U8 => encode_int!(u8),
+            Dtype::I16 => encode_int!(i16),
+            Dtype::U16 => encode_int!(u16),
+            Dtype::I32 => encode_int!(i32),
+            Dtype::U32 => encode_int!(u32),
+            Dtype::F32 => out.extend((value as f32).to_le_bytes()),
+            Dtype::F64 => out.extend(value.to_le_bytes()),
+        }
+        Ok(())
+    }
+}
+
+/// NPY trace source whose element type is read from the header.
+pub struct NpySource {
+    mmap: Mmap,
+    header: NpyHeader,
+    dtype: Dtype,
+    window: Range<usize>,
+}
+
+impl NpySource {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path)?;
+        /* Safety: the file must not be modified while it is mapped */
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header = NpyHeader::parse(&mmap)?;
+        let dtype = Dtype::from_descr(&header.descr)
+            .ok_or_else(|| invalid(&format!("{path} holds unsupported {} values", header.descr)))?;
+        match header.data_end(dtype.size()) {
+            Some(end) if end <= mmap.len() => {}
+            _ => return Err(invalid(&format!("{path} is truncated"))),
+        }
+        Ok(Self {
+            window: 0..header.shape.1,
+            mmap,
+            header,
+            dtype,
+        })
+    }
+
+    pub fn dtype(&self) -> Dtype {
+        self.dtype
+    }
+
+    pub fn header(&self) -> &NpyHeader {
+        &self.header
+    }
+
+    /// Restricts the returned traces to the samples start..end.
+    pub fn window(&mut self, start: usize, end: usize) {
+        assert!(start <= end && end <= self.header.shape.1);
+        self.window = start..end;
+    }
+
+    pub fn len_traces(&self) -> usize {
+        self.header.shape.0
+    }
+
+    /// Number of samples in the window.
+    pub fn len_samples(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Traces start..start + len (clipped to the file), restricted to the
+    /// window and converted to `A` (e.g. f32 samples or usize metadata).
+    /// Values that `A` cannot represent, e.g. negative, large or fractional
+    /// values read as u8, are an error rather than being replaced.
+    pub fn batch<A: FromPrimitive + Zero + Copy>(
+        &self,
+        start: usize,
+        len: usize,
+    ) -> Result<Array2<A>> {
+        let (rows, cols) = self.header.shape;
+        let end = (start + len).min(rows);
+        let start = start.min(end);
+        let size = self.dtype.size();
+        let data = &self.mmap[self.header.data_offset..];
+        let mut out = Array2::zeros((end - start, self.len_samples()));
+        /* Integer types truncate 0.5 to 0 */
+        let integer = A::from_f64(0.5).is_some_and(|half| half.is_zero());
+        for ((r, c), value) in out.indexed_iter_mut() {
+            let (row, col) = (start + r, self.window.start + c);
+            let index = if self.header.fortran_order {
+                col * rows + row
+            } else {
+                row * cols + col
+            };
+            let x = self
+                .dtype
+                .decode(&data[index * size..], self.header.big_endian);
+            *value = A::from_f64(x)
+                .filter(|_| !integer || x.fract() == 0.0)
+                .ok_or_else(|| {
+                    invalid(&format!(
+                        "value {x} of trace {row}, sample {col} does not fit in {}",
+                        std::any::type_name::<A>()
+                    ))
+                })?;
+        }
+        Ok(out)
+    }
+
+    /// Batches of `len` traces in order.
+    pub fn batches<A: FromPrimitive + Zero + Copy>(
+        &self,
+        len: usize,
+    ) -> impl Iterator<Item = Result<Array2<A>>> + '_ {
+        (0..self.len_traces())
+            .step_by(len)
+            .map(move |start| self.batch(start, len))
+    }
+}
+
+/// Writes 2-D NPY files (C order, little-endian) one batch of rows at a
+/// time. The number of rows is written in the header by `finish`.
+pub struct NpyWriter {
+    writer: BufWriter<File>,
+    dtype: Dtype,
+    len_samples: usize,
+    len_traces: usize,
+}
+
+impl NpyWriter {
+    /// Header length, large enough for any shape.
+    const HEADER_LEN: usize = 128;
+
+    pub fn create(path: &str, len_samples: usize, dtype: Dtype) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&Self::header(dtype, 0, len_samples))?;
+        Ok(Self {
+            writer,
+            dtype,
+            len_samples,
+            len_traces: 0,
+        })
+    }
+
+    fn header(dtype: Dtype, rows: usize, cols: usize) -> Vec<u8> {
+        let order = if dtype.size() == 1 { '|' } else { '<' };
+        let dict = format!(
+            "{{'descr': '{order}{}', 'fortran_order': False, 'shape': ({rows}, {cols}), }}",
+            dtype.descr()
+        );
+        let mut header = b"\x93NUMPY\x01\x00".to_vec();
+        header.extend(((Self::HEADER_LEN - 10) as u16).to_le_bytes());
+        header.extend(dict.bytes());
+        header.resize(Self::HEADER_LEN - 1, b' ');
+        header.push(b'\n');
+        header
+    }
+
+    /// Appends a batch of traces (one per row), converted to the file type.
+    /// Samples that the type cannot hold exactly, e.g. volts in an integer
+    /// file, are an error.
+    pub fn write_batch(&mut self, traces: ArrayView2<f32>) -> Result<()> {
+        if traces.shape()[1] != self.len_samples {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "batch width does not match the file",
+            ));
+        }
+        let mut bytes = Vec::with_capacity(self.len_samples * self.dtype.size());
+        for trace in traces.outer_iter() {
+            bytes.clear();
+            for sample in trace.iter() {
+                self.dtype.encode(*sample as f64, &mut bytes)?;
+            }
+            self.writer.write_all(&bytes)?;
+        }
+        self.len_traces += traces.shape()[0];
+        Ok(())
+    }
+
+    /// Appends rows of bytes (e.g. metadata) to a u8 file.
+    pub fn write_bytes(&mut self, rows: ArrayView2<u8>) -> Result<()> {
+        if self.dtype != Dtype::U8 || rows.shape()[1] != self.len_samples {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "bytes do not match the file type or width",
+            ));
+        }
+        for row in rows.outer_iter() {
+            self.writer.write_all(&row.to_vec())?;
+        }
+        self.len_traces += rows.shape()[0];
+        Ok(())
+    }
+
+    /// Writes the number of rows in the header and flushes the file.
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer
+            .write_all(&Self::header(self.dtype, self.len_traces, self.len_samples))?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    /// Writes an NPY file with the header `dict`, padded to `len_header`
+    /// bytes, followed by `data`.
+    fn npy_file(name: &str, dict: &str, len_header: usize, data: &[u8]) -> String {
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend(((len_header - 10) as u16).to_le_bytes());
+        bytes.extend(dict.bytes());
+        bytes.resize(len_header - 1, b' ');
+        bytes.push(b'\n');
+        bytes.extend(data);
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn layouts() {
+        let expected = array![[1i16, 2, 3], [4, 5, 6]];
+        let values = |order: [i16; 6], big_endian: bool| -> Vec<u8> {
+            order
+                .iter()
+                .flat_map(|v| {
+                    if big_endian {
+                        v.to_be_bytes()
+                    } else {
+                        v.to_le_bytes()
+                    }
+                })
+                .collect()
+        };
+        for (name, descr, fortran, data) in [
+            (
+                "cpa_c.npy",
+                "<i2",
+                "False",
+                values([1, 2, 3, 4, 5, 6], false),
+            ),
+            (
+                "cpa_fortran.npy",
+                "<i2",
+                "True",
+                values([1, 4, 2, 5, 3, 6], false),
+            ),
+            (
+                "cpa_big.npy",
+                ">i2",
+                "False",
+                values([1, 2, 3, 4, 5, 6], true),
+            ),
+        ] {
+            let dict =
+                format!("{{'descr': '{descr}', 'fortran_order': {fortran}, 'shape': (2, 3), }}");
+            let path = npy_file(name, &dict, 128, &data);
+            let mut reader = NpyReader::<i16>::open(&path).unwrap();
+            assert_eq!(reader.view(), expected);
+            reader.window(1, 3);
+            assert_eq!(reader.chunk(1, 1), array![[5, 6]]);
+            let source = NpySource::open(&path).unwrap();
+            assert_eq!(source.batch::<i16>(0, 2).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn invalid_files() {
+        let dict = "{'descr': '<f4', 'fortran_order': False, 'shape': (1, 2), }";
+        let path = npy_file("cpa_misaligned.npy", dict, 130, &[0; 8]);
+        assert!(NpyReader::<f32>::open(&path).is_err());
+        assert!(NpySource::open(&path).is_ok());
+        let dict = format!(
+            "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, 2), }}",
+            usize::MAX / 2
+        );
+        let path = npy_file("cpa_overflow.npy", &dict, 128, &[0; 8]);
+        assert!(NpyReader::<f32>::open(&path).is_err());
+        assert!(NpySource::open(&path).is_err());
+    }
+
+    #[test]
+    fn conversions() {
+        let dict = "{'descr': '<f4', 'fortran_order': False, 'shape': (1, 3), }";
+        let data: Vec<u8> = [1.0f32, 2.5, -1.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let path = npy_file("cpa_conversions.npy", dict, 128, &data);
+        let mut source = NpySource::open(&path).unwrap();
+        assert_eq!(source.batch::<f32>(0, 1).unwrap(), array![[1.0, 2.5, -1.0]]);
+        assert_eq!(
+            source.batch::<i8>(0, 1).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        source.window(0, 1);
+        assert_eq!(source.batch::<usize>(0, 1).unwrap(), array![[1]]);
+        source.window(1, 2);
+        assert!(source.batch::<usize>(0, 1).is_err());
+        source.window(2, 3);
+        assert!(source.batch::<u8>(0, 1).is_err());
+        assert_eq!(source.batch::<i16>(0, 1).unwrap(), array![[-1]]);
+    }
+}
@@ -0,0 +1,83 @@
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+/* Preprocessing pipeline placed between the trace reader and the update
+calls of the engines: every stage receives a batch of traces (one trace per
+row) with its metadata and returns the processed batch, which may have fewer
+rows (rejected traces) or a different number of samples (e.g. spectra or
+compressed traces). */
+
+pub trait Stage {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>);
+
+    /// Number of samples per trace at the output for `len_in` samples at the input.
+    fn len_samples(&self, len_in: usize) -> usize {
+        len_in
+    }
+}
+
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self { stages: Vec::new() }
+    }
+
+    /// Appends a stage, the stages are applied in the order they are added.
+    pub fn add(&mut self, stage: impl Stage + 'static) {
+        self.stages.push(Box::new(stage));
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+}
+
+impl Stage for Pipeline {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        let mut batch = (traces, metadata);
+        for stage in self.stages.iter_mut() {
+            batch = stage.process(batch.0, batch.1);
+        }
+        batch
+    }
+
+    fn len_samples(&self, len_in: usize) -> usize {
+        self.stages
+            .iter()
+            .fold(len_in, |len, stage| stage.len_samples(len))
+    }
+}
+
+/// Applies `f` to every trace of the batch in parallel, `len_out` being the
+/// number of samples returned by `f`.
+pub fn map_rows(
+    traces: ArrayView2<f32>,
+    len_out: usize,
+    f: impl Fn(ArrayView1<f32>) -> Array1<f32> + Sync,
+) -> Array2<f32> {
+    let rows: Vec<Array1<f32>> = (0..traces.shape()[0])
+        .into_par_iter()
+        .map(|i| f(traces.row(i)))
+        .collect();
+    let mut out_traces = Array2::zeros((rows.len(), len_out));
+    for (i, row) in rows.iter().enumerate() {
+        out_traces.row_mut(i).assign(row);
+    }
+    out_traces
+}
@@ -0,0 +1,206 @@
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2, Axis};
+
+/* Points of interest: leakage detection curves accumulated over batches of
+traces, selection of sample indices from any curve (SNR, NICV, t-test or
+a correlation row) and an adaptor keeping only those samples before the
+traces are passed to an engine */
+
+pub struct Snr {
+    sum: Array2<f64>,
+    sum2: Array2<f64>,
+    count: Array1<usize>,
+    len_samples: usize,
+}
+
+impl Snr {
+    /// Accumulates per-class statistics of `size` samples for labels in 0..classes.
+    pub fn new(size: usize, classes: usize) -> Self {
+        Self {
+            sum: Array2::zeros((classes, size)),
+            sum2: Array2::zeros((classes, size)),
+            count: Array1::zeros(classes),
+            len_samples: size,
+        }
+    }
+
+    pub fn update<T: Copy>(&mut self, traces: ArrayView2<T>, labels: ArrayView1<usize>)
+    where
+        f64: From<T>,
+    {
+        for (trace, label) in traces.axis_iter(Axis(0)).zip(labels.iter()) {
+            self.count[*label] += 1;
+            for i in 0..self.len_samples {
+                let x = f64::from(trace[i]);
+                self.sum[[*label, i]] += x;
+                self.sum2[[*label, i]] += x * x;
+            }
+        }
+    }
+
+    /// Returns (variance of the class means, mean of the class variances,
+    /// overall variance) for each sample.
+    fn moments(&self) -> (Array1<f64>, Array1<f64>, Array1<f64>) {
+        let total = self.count.sum() as f64;
+        let mut signal = Array1::zeros(self.len_samples);
+        let mut noise = Array1::zeros(self.len_samples);
+        let mut variance = Array1::zeros(self.len_samples);
+        for i in 0..self.len_samples {
+            let mean = self.sum.column(i).sum() / total;
+            variance[i] = self.sum2.column(i).sum() / total - mean * mean;
+            for class in 0..self.count.len() {
+                let n = self.count[class] as f64;
+                if n == 0.0 {
+                    continue;
+                }
+                let class_mean = self.sum[[class, i]] / n;
+                let class_var = self.sum2[[class, i]] / n - class_mean * class_mean;
+                signal[i] += n / total * (class_mean - mean) * (class_mean - mean);
+                noise[i] += n / total * class_var;
+            }
+        }
+        (signal, noise, variance)
+    }
+
+    /// Signal-to-noise ratio Var(E[L|X]) / E[Var(L|X)].
+    pub fn snr(&self) -> Array1<f32> {
+        let (signal, noise, _) = self.moments();
+        (&signal / &noise).map(|x| *x as f32)
+    }
+
+    /// Normalized inter-class variance Var(E[L|X]) / Var(L).
+    pub fn nicv(&self) -> Array1<f32> {
+        let (signal, _, variance) = self.moments();
+        (&signal / &variance).map(|x| *x as f32)
+    }
+}
+
+pub struct TTest {
+    sum: Array2<f64>,
+    sum2: Array2<f64>,
+    count: [usize; 2],
+    len_samples: usize,
+}
+
+impl TTest {
+    /// Welch's t-test between two groups of traces (e.g. fixed vs random).
+    pub fn new(size: usize) -> Self {
+        Self {
+            sum: Array2::zeros((2, size)),
+            sum2: Array2::zeros((2, size)),
+            count: [0, 0],
+            len_samples: size,
+        }
+    }
+
+    pub fn update<T: Copy>(&mut self, traces: ArrayView2<T>, groups: ArrayView1<bool>)
+    where
+        f64: From<T>,
+    {
+        for (trace, group) in traces.axis_iter(Axis(0)).zip(groups.iter()) {
+            let g = *group as usize;
+            self.count[g] += 1;
+            for i in 0..self.len_samples {
+                let x = f64::from(trace[i]);
+                self.sum[[g, i]] += x;
+                self.sum2[[g, i]] += x * x;
+            }
+        }
+    }
+
+    pub fn t(&self) -> Array1<f32> {
+        let (n0, n1) = (self.count[0] as f64, self.count[1] as f64);
+        Array1::from_shape_fn(self.len_samples, |i| {
+            let (m0, m1) = (self.sum[[0, i]] / n0, self.sum[[1, i]] / n1);
+            let v0 = self.sum2[[0, i]] / n0 - m0 * m0;
+            let v1 = self.sum2[[1, i]] / n1 - m1 * m1;
+            ((m0 - m1) / (v0 / n0 + v1 / n1).sqrt()) as f32
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Selection {
+    /// Every sample whose absolute value reaches the threshold.
+    Threshold(f32),
+    /// The N samples with the largest absolute values.
+    TopN(usize),
+    /// The N largest samples, at least `spacing` samples apart.
+    TopNSpaced(usize, usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct Poi {
+    indices: Vec<usize>,
+}
+
+impl Poi {
+    pub fn new(mut indices: Vec<usize>) -> Self {
+        indices.sort_unstable();
+        indices.dedup();
+        Self { indices }
+    }
+
+    /// Picks sample indices from a leakage curve, NaN samples are ignored.
+    pub fn select(curve: ArrayView1<f32>, selection: Selection) -> Self {
+        let mut by_value: Vec<usize> = (0..curve.len()).filter(|i| !curve[*i].is_nan()).collect();
+        by_value.sort_by(|a, b| curve[*b].abs().total_cmp(&curve[*a].abs()));
+        let indices = match selection {
+            Selection::Threshold(threshold) => by_value
+                .into_iter()
+                .filter(|i| curve[*i].abs() >= threshold)
+                .collect(),
+            Selection::TopN(n) => by_value.into_iter().take(n).collect(),
+            Selection::TopNSpaced(n, spacing) => {
+                let mut picked: Vec<usize> = Vec::with_capacity(n);
+                for i in by_value {
+                    if picked.len() == n {
+                        break;
+                    }
+                    if picked.iter().all(|p| p.abs_diff(i) >= spacing) {
+                        picked.push(i);
+                    }
+                }
+                picked
+            }
+        };
+        Self::new(indices)
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    /// Sample index in the original trace of the `index`-th point of interest.
+    pub fn original_index(&self, index: usize) -> usize {
+        self.indices[index]
+    }
+
+    /// Keeps the points of interest of a batch of traces, e.g. before
+    /// cpa_normal::Cpa::update with `size = poi.len()`.
+    pub fn apply<T: Clone>(&self, traces: ArrayView2<T>) -> Array2<T> {
+        traces.select(Axis(1), &self.indices)
+    }
+
+    /// Keeps the points of interest of a single trace (cpa_single, cpa_partition).
+    pub fn apply_row<T: Clone>(&self, trace: ArrayView1<T>) -> Array1<T> {
+        trace.select(Axis(0), &self.indices)
+    }
+
+    /// Spreads a curve computed on the points of interest (e.g. a row of
+    /// the correlation array) back onto `size` original samples.
+    pub fn expand(&self, curve: ArrayView1<f32>, size: usize) -> Array1<f32> {
+        let mut full = Array1::zeros(size);
+        for (i, index) in self.indices.iter().enumerate() {
+            full[*index] = curve[i];
+        }
+        full
+    }
+}
@@ -0,0 +1,354 @@
+/* ML-KEM (Kyber) and ML-DSA (Dilithium) arithmetic used to build leakage
+models of the coefficient-wise multiplications in the NTT domain, following
+the reference implementations and FIPS 203/204. A guess is a secret NTT
+coefficient in 0..q, hence guess ranges of 3329 and 8380417. Cpa and
+Cpa_partition allocate guess_range x len_samples correlations, which is not
+possible for q = 8380417: Dilithium models must be run with
+cpa_tiled::Cpa_tiled, which only holds one tile of guesses at a time. */
+
+use crate::leakage::hw;
+
+pub const KYBER_Q: i32 = 3329;
+pub const KYBER_QINV: i16 = -3327; // q^-1 mod 2^16
+pub const KYBER_ROOT: i64 = 17;
+pub const DILITHIUM_Q: i32 = 8380417;
+pub const DILITHIUM_QINV: i32 = 58728449; // q^-1 mod 2^32
+pub const DILITHIUM_ROOT: i64 = 1753;
+
+/// Montgomery reduction of a 32-bit value, returns a * 2^-16 mod q in (-q, q).
+pub fn kyber_montgomery_reduce(a: i32) -> i16 {
+    let t = (a as i16).wrapping_mul(KYBER_QINV);
+    ((a - t as i32 * KYBER_Q) >> 16) as i16
+}
+
+/// Multiplication followed by Montgomery reduction (`fqmul` in the reference code).
+pub fn kyber_fqmul(a: i16, b: i16) -> i16 {
+    kyber_montgomery_reduce(a as i32 * b as i32)
+}
+
+/// Barrett reduction of a 16-bit value, returns the centered representative.
+pub fn kyber_barrett_reduce(a: i16) -> i16 {
+    let v: i32 = ((1 << 26) + KYBER_Q / 2) / KYBER_Q;
+    let t = ((v * a as i32 + (1 << 25)) >> 26) * KYBER_Q;
+    (a as i32 - t) as i16
+}
+
+/// Multiplication followed by a 32-bit Barrett reduction, returns a value in [0, q).
+pub fn kyber_barrett_mul(a: i16, b: i16) -> i16 {
+    let product = a as i64 * b as i64;
+    let v: i64 = (1 << 44) / KYBER_Q as i64;
+    let mut r = product - ((product * v) >> 44) * KYBER_Q as i64;
+    while r >= KYBER_Q as i64 {
+        r -= KYBER_Q as i64;
+    }
+    while r < 0 {
+        r += KYBER_Q as i64;
+    }
+    r as i16
+}
+
+/// Montgomery reduction of a 64-bit value, returns a * 2^-32 mod q in (-q, q).
+pub fn dilithium_montgomery_reduce(a: i64) -> i32 {
+    let t = (a as i32).wrapping_mul(DILITHIUM_QINV);
+    ((a - t as i64 * DILITHIUM_Q as i64) >> 32) as i32
+}
+
+/// Pointwise multiplication of two coefficients as in `poly_pointwise_montgomery`.
+pub fn dilithium_pointwise(a: i32, b: i32) -> i32 {
+    dilithium_montgomery_reduce(a as i64 * b as i64)
+}
+
+/// Reduction to a representative in [-6283008, 6283008] (`reduce32`), for a
+/// value up to 2^31 - 2^22 - 1.
+pub fn dilithium_reduce32(a: i32) -> i32 {
+    let t = (a + (1 << 22)) >> 23;
+    a - t * DILITHIUM_Q
+}
+
+/// Multiplication followed by a 64-bit Barrett reduction, returns a value in [0, q).
+pub fn dilithium_barrett_mul(a: i32, b: i32) -> i32 {
+    let product = a as i128 * b as i128;
+    let v: i128 = (1 << 70) / DILITHIUM_Q as i128;
+    let mut r = product - ((product * v) >> 70) * DILITHIUM_Q as i128;
+    while r >= DILITHIUM_Q as i128 {
+        r -= DILITHIUM_Q as i128;
+    }
+    while r < 0 {
+        r += DILITHIUM_Q as i128;
+    }
+    r as i32
+}
+
+/// Hamming weight of the two's complement 16-bit representation.
+pub fn hw_i16(value: i16) -> usize {
+    hw(value as u16 as usize)
+}
+
+/// Hamming weight of the two's complement 32-bit representation.
+pub fn hw_i32(value: i32) -> usize {
+    hw(value as u32 as usize)
+}
+
+/// Hamming weight of the Kyber `fqmul` output of a known coefficient and a guess.
+pub fn kyber_fqmul_hw(known: usize, guess: usize) -> usize {
+    hw_i16(kyber_fqmul(known as i16, guess as i16))
+}
+
+/// Hamming weight of the Kyber Barrett product of a known coefficient and a guess.
+pub fn kyber_barrett_hw(known: usize, guess: usize) -> usize {
+    hw_i16(kyber_barrett_mul(known as i16, guess as i16))
+}
+
+/// Hamming weight of the Dilithium Montgomery product of a known coefficient and a guess.
+/// The guess range is q, use it with `Cpa_tiled`.
+pub fn dilithium_pointwise_hw(known: usize, guess: usize) -> usize {
+    hw_i32(dilithium_pointwise(known as i32, guess as i32))
+}
+
+/// Hamming weight of the Dilithium Barrett product of a known coefficient and a guess.
+/// The guess range is q, use it with `Cpa_tiled`.
+pub fn dilithium_barrett_hw(known: usize, guess: usize) -> usize {
+    hw_i32(dilithium_barrett_mul(known as i32, guess as i32))
+}
+
+/// Maps the recovered guesses (one per coefficient) to a polynomial with
+/// coefficients in [0, q), removing a Montgomery factor `2^r` when the
+/// attacked value was stored in Montgomery form (`r` = 0 otherwise).
+pub fn assemble_polynomial(guesses: &[i32], q: i32, montgomery_bits: u32) -> Vec<i32> {
+    let factor = pow_mod(
+        pow_mod(2, montgomery_bits as i64, q as i64),
+        q as i64 - 2,
+        q as i64,
+    );
+    guesses
+        .iter()
+        .map(|g| ((*g as i64).rem_euclid(q as i64) * factor % q as i64) as i32)
+        .collect()
+}
+
+/// Centers the coefficients of a polynomial into (-q/2, q/2].
+pub fn center_polynomial(coefficients: &[i32], q: i32) -> Vec<i32> {
+    coefficients
+        .iter()
+        .map(|c| {
+            let c = c.rem_euclid(q);
+            if c > q / 2 {
+                c - q
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+fn pow_mod(base: i64, exp: i64, q: i64) -> i64 {
+    let (mut result, mut base, mut exp) = (1, base.rem_euclid(q), exp);
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result * base % q;
+        }
+        base = base * base % q;
+        exp >>= 1;
+    }
+    result
+}
+
+fn bit_reverse(value: usize, bits: u32) -> usize {
+    value.reverse_bits() >> (usize::BITS - bits)
+}
+
+fn ntt(coefficients: &[i32], q: i64, root: i64, bits: u32) -> Vec<i32> {
+    let mut f: Vec<i64> = coefficients
+        .iter()
+        .map(|c| (*c as i64).rem_euclid(q))
+        .collect();
+    let n = f.len();
+    let mut k = 1;
+    let mut len = n / 2;
+    while len >= n >> bits {
+        for start in (0..n).step_by(2 * len) {
+            let zeta = pow_mod(root, bit_reverse(k, bits) as i64, q);
+            k += 1;
+            for j in start..start + len {
+                let t = zeta * f[j + len] % q;
+                f[j + len] = (f[j] - t).rem_euclid(q);
+                f[j] = (f[j] + t) % q;
+            }
+        }
+        len /= 2;
+    }
+    f.into_iter().map(|c| c as i32).collect()
+}
+
+fn inverse_ntt(coefficients: &[i32], q: i64, root: i64, bits: u32) -> Vec<i32> {
+    let mut f: Vec<i64> = coefficients
+        .iter()
+        .map(|c| (*c as i64).rem_euclid(q))
+        .collect();
+    let n = f.len();
+    let mut k = (1 << bits) - 1;
+    let mut len = n >> bits;
+    while len <= n / 2 {
+        for start in (0..n).step_by(2 * len) {
+            let zeta = pow_mod(root, bit_reverse(k, bits) as i64, q);
+            k -= 1;
+            for j in start..start + len {
+                let t = f[j];
+                f[j] = (t + f[j + len]) % q;
+                f[j + len] = zeta * (f[j + len] - t).rem_euclid(q) % q;
+            }
+        }
+        len *= 2;
+    }
+    let scale = pow_mod(1 << bits, q - 2, q);
+    f.into_iter().map(|c| (c * scale % q) as i32).collect()
+}
+
+/// Forward NTT of a 256-coefficient polynomial as in FIPS 203.
+pub fn kyber_ntt(coefficients: &[i32]) -> Vec<i32> {
+    ntt(coefficients, KYBER_Q as i64, KYBER_ROOT, 7)
+}
+
+/// Inverse NTT of a 256-coefficient polynomial as in FIPS 203.
+pub fn kyber_inverse_ntt(coefficients: &[i32]) -> Vec<i32> {
+    inverse_ntt(coefficients, KYBER_Q as i64, KYBER_ROOT, 7)
+}
+
+/// Forward NTT of a 256-coefficient polynomial as in FIPS 204.
+pub fn dilithium_ntt(coefficients: &[i32]) -> Vec<i32> {
+    ntt(coefficients, DILITHIUM_Q as i64, DILITHIUM_ROOT, 8)
+}
+
+/// Inverse NTT of a 256-coefficient polynomial as in FIPS 204.
+pub fn dilithium_inverse_ntt(coefficients: &[i32]) -> Vec<i32> {
+    inverse_ntt(coefficients, DILITHIUM_Q as i64, DILITHIUM_ROOT, 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zeta(root: i64, k: usize, q: i64, bits: u32, montgomery_bits: u32) -> i32 {
+        let zeta =
+            pow_mod(root, bit_reverse(k, bits) as i64, q) * pow_mod(2, montgomery_bits as i64, q);
+        center_polynomial(&[(zeta % q) as i32], q as i32)[0]
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!((KYBER_QINV as i32).wrapping_mul(KYBER_Q) as i16, 1);
+        assert_eq!(DILITHIUM_QINV.wrapping_mul(DILITHIUM_Q), 1);
+        /* Primitive 256th and 512th roots of unity */
+        assert_eq!(pow_mod(KYBER_ROOT, 128, KYBER_Q as i64), KYBER_Q as i64 - 1);
+        assert_eq!(
+            pow_mod(DILITHIUM_ROOT, 256, DILITHIUM_Q as i64),
+            DILITHIUM_Q as i64 - 1
+        );
+        /* First zetas of the reference implementations, in Montgomery form */
+        let kyber: Vec<i32> = (0..4)
+            .map(|k| zeta(KYBER_ROOT, k, KYBER_Q as i64, 7, 16))
+            .collect();
+        assert_eq!(kyber, [-1044, -758, -359, -1517]);
+        let dilithium: Vec<i32> = (1..5)
+            .map(|k| zeta(DILITHIUM_ROOT, k, DILITHIUM_Q as i64, 8, 32))
+            .collect();
+        assert_eq!(dilithium, [25847, -2608894, -518909, 237124]);
+    }
+
+    #[test]
+    fn kyber_reductions() {
+        let q = KYBER_Q as i64;
+        let r = 1 << 16;
+        for a in (-(KYBER_Q << 15)..KYBER_Q << 15).step_by(997) {
+            let m = kyber_montgomery_reduce(a) as i64;
+            assert!(m.abs() < q);
+            assert_eq!((m * r - a as i64).rem_euclid(q), 0);
+        }
+        for a in i16::MIN..=i16::MAX {
+            let b = kyber_barrett_reduce(a) as i64;
+            assert!(b.abs() <= q / 2);
+            assert_eq!((b - a as i64).rem_euclid(q), 0);
+        }
+        for (a, b) in [(0, 0), (1, 1), (3328, 3328), (-3328, 17), (1234, -2345)] {
+            let expected = (a as i64 * b as i64).rem_euclid(q);
+            assert_eq!(kyber_barrett_mul(a, b) as i64, expected);
+            let fqmul = kyber_fqmul(a, b) as i64;
+            assert_eq!((fqmul * r - a as i64 * b as i64).rem_euclid(q), 0);
+        }
+    }
+
+    #[test]
+    fn dilithium_reductions() {
+        let q = DILITHIUM_Q as i64;
+        let r = 1i128 << 32;
+        for a in [
+            0,
+            1,
+            -1,
+            (q << 31) - 1,
+            -(q << 31) + 1,
+            123_456_789_012,
+            -987_654_321_098,
+        ] {
+            let m = dilithium_montgomery_reduce(a) as i64;
+            assert!(m.abs() < q);
+            assert_eq!((m as i128 * r - a as i128).rem_euclid(q as i128), 0);
+        }
+        for a in [0, 1, -1, i32::MIN, i32::MAX - (1 << 22), 8380417, -8380417] {
+            let b = dilithium_reduce32(a) as i64;
+            assert!((-6283008..=6283008).contains(&b));
+            assert_eq!((b - a as i64).rem_euclid(q), 0);
+        }
+        for (a, b) in [(0, 0), (1, 1), (8380416, 8380416), (-8380416, 1753)] {
+            let expected = (a as i64 * b as i64).rem_euclid(q);
+            assert_eq!(dilithium_barrett_mul(a, b) as i64, expected);
+            let pointwise = dilithium_pointwise(a, b) as i64;
+            assert_eq!(
+                (pointwise as i128 * r - a as i128 * b as i128).rem_euclid(q as i128),
+                0
+            );
+        }
+    }
+
+    fn polynomial(q: i32) -> Vec<i32> {
+        (0..256).map(|i| (i * i * 7919 + 13) % q).collect()
+    }
+
+    #[test]
+    fn ntt_round_trips() {
+        let f = polynomial(KYBER_Q);
+        assert_eq!(kyber_inverse_ntt(&kyber_ntt(&f)), f);
+        let f = polynomial(DILITHIUM_Q);
+        assert_eq!(dilithium_inverse_ntt(&dilithium_ntt(&f)), f);
+    }
+
+    #[test]
+    fn ntt_known_answers() {
+        /* The constant 1 maps to 1 in every slot: the degree 1 remainders of
+        ML-KEM and the evaluations of ML-DSA */
+        let mut one = vec![0; 256];
+        one[0] = 1;
+        let kyber: Vec<i32> = (0..256).map(|i| (i % 2 == 0) as i32).collect();
+        assert_eq!(kyber_ntt(&one), kyber);
+        assert_eq!(dilithium_ntt(&one), vec![1; 256]);
+        /* x * x^255 = -1 modulo x^256 + 1, and ML-DSA multiplies pointwise */
+        let (mut x, mut x255) = (vec![0; 256], vec![0; 256]);
+        x[1] = 1;
+        x255[255] = 1;
+        let product: Vec<i32> = dilithium_ntt(&x)
+            .iter()
+            .zip(dilithium_ntt(&x255))
+            .map(|(a, b)| (*a as i64 * b as i64 % DILITHIUM_Q as i64) as i32)
+            .collect();
+        assert_eq!(product, vec![DILITHIUM_Q - 1; 256]);
+    }
+
+    #[test]
+    fn assemble_and_center() {
+        /* A guess in Montgomery form with r = 16 is c * 2^16 mod q */
+        let guesses = [(5 << 16) % KYBER_Q, (3324 << 16) % KYBER_Q];
+        let polynomial = assemble_polynomial(&guesses, KYBER_Q, 16);
+        assert_eq!(polynomial, [5, 3324]);
+        assert_eq!(center_polynomial(&polynomial, KYBER_Q), [5, -5]);
+    }
+}
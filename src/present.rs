@@ -0,0 +1,195 @@
+/* PRESENT-80/128 primitives used to build leakage models, as specified in:
+https://www.iacr.org/archive/ches2007/47270450/47270450.pdf
+Nibble i of the state is made of bits 4i+3..4i, the round key of round r
+(starting at 1) is the leftmost 64 bits of the key register. */
+
+pub const SBOX: [u8; 16] = [
+    0xC, 0x5, 0x6, 0xB, 0x9, 0x0, 0xA, 0xD, 0x3, 0xE, 0xF, 0x8, 0x4, 0x7, 0x1, 0x2,
+];
+
+pub const INV_SBOX: [u8; 16] = [
+    0x5, 0xE, 0xF, 0x8, 0xC, 0x1, 0x2, 0xD, 0xB, 0x4, 0x6, 0x3, 0x0, 0x7, 0x9, 0xA,
+];
+
+pub const ROUNDS: usize = 31;
+
+pub fn sbox(index: u8) -> u8 {
+    SBOX[(index & 0xF) as usize]
+}
+
+pub fn inv_sbox(index: u8) -> u8 {
+    INV_SBOX[(index & 0xF) as usize]
+}
+
+pub fn s_layer(state: u64) -> u64 {
+    (0..16).fold(0, |acc, i| {
+        acc | ((sbox(((state >> (4 * i)) & 0xF) as u8) as u64) << (4 * i))
+    })
+}
+
+pub fn p_layer(state: u64) -> u64 {
+    let mut out: u64 = 0;
+    for i in 0..64 {
+        let target = if i == 63 { 63 } else { (16 * i) % 63 };
+        out |= ((state >> i) & 1) << target;
+    }
+    out
+}
+
+/// One PRESENT round: key addition, substitution layer and permutation layer.
+pub fn round(state: u64, round_key: u64) -> u64 {
+    p_layer(s_layer(state ^ round_key))
+}
+
+pub fn encrypt(plaintext: u64, round_keys: &[u64]) -> u64 {
+    let mut state = plaintext;
+    for round_key in &round_keys[..ROUNDS] {
+        state = round(state, *round_key);
+    }
+    state ^ round_keys[ROUNDS]
+}
+
+fn rotl_80(register: u128, shift: u32) -> u128 {
+    let mask: u128 = (1 << 80) - 1;
+    ((register << shift) | (register >> (80 - shift))) & mask
+}
+
+/// Returns the 32 round keys derived from an 80-bit key.
+pub fn key_schedule_80(key: u128) -> Vec<u64> {
+    let mut register = key & ((1 << 80) - 1);
+    let mut round_keys = Vec::with_capacity(ROUNDS + 1);
+    for counter in 1..=ROUNDS + 1 {
+        round_keys.push((register >> 16) as u64);
+        register = rotl_80(register, 61);
+        let top = sbox((register >> 76) as u8) as u128;
+        register = (register & !(0xF << 76)) | (top << 76);
+        register ^= (counter as u128) << 15;
+    }
+    round_keys
+}
+
+/// Returns the 32 round keys derived from a 128-bit key.
+pub fn key_schedule_128(key: u128) -> Vec<u64> {
+    let mut register = key;
+    let mut round_keys = Vec::with_capacity(ROUNDS + 1);
+    for counter in 1..=ROUNDS + 1 {
+        round_keys.push((register >> 64) as u64);
+        register = register.rotate_left(61);
+        let top = ((sbox((register >> 124) as u8) as u128) << 124)
+            | ((sbox((register >> 120) as u8) as u128) << 120);
+        register = (register & !(0xFF << 120)) | top;
+        register ^= (counter as u128) << 62;
+    }
+    round_keys
+}
+
+fn inv_sbox_top(round_key: u64, nibbles: usize) -> u64 {
+    (0..nibbles).fold(round_key, |acc, n| {
+        let shift = 60 - 4 * n;
+        (acc & !(0xF << shift)) | ((inv_sbox((acc >> shift) as u8) as u64) << shift)
+    })
+}
+
+/// Recovers the 80-bit key from the round keys of rounds `round` and `round + 1`.
+pub fn invert_key_schedule_80(round_key: u64, next_round_key: u64, round: usize) -> u128 {
+    /* The 16 bits missing from round_key are rotated into the top of the
+    next round key, below the nibble that went through the sbox */
+    let low = ((inv_sbox_top(next_round_key, 1) >> 45) & 0xFFFF) as u128;
+    let mut register = ((round_key as u128) << 16) | low;
+    for counter in (1..round).rev() {
+        register ^= (counter as u128) << 15;
+        let top = inv_sbox((register >> 76) as u8) as u128;
+        register = (register & !(0xF << 76)) | (top << 76);
+        register = rotl_80(register, 19);
+    }
+    register
+}
+
+/// Recovers the 128-bit key from the round keys of rounds `round` to `round + 2`.
+pub fn invert_key_schedule_128(round_keys: [u64; 3], round: usize) -> u128 {
+    /* Bits 63..3 of the register come from the next round key and bits 2..0
+    from the one after it, once the sbox and the round counter are undone */
+    let counter = round as u64;
+    let next = inv_sbox_top(round_keys[1], 2) ^ ((counter >> 2) & 0x7);
+    let after = (inv_sbox_top(round_keys[2], 2) >> 58) & 0x7;
+    let low = ((next & ((1 << 61) - 1)) << 3) | (after ^ ((counter & 0x3) << 1));
+    let mut register = ((round_keys[0] as u128) << 64) | low as u128;
+    for counter in (1..round).rev() {
+        register ^= (counter as u128) << 62;
+        let top = ((inv_sbox((register >> 124) as u8) as u128) << 124)
+            | ((inv_sbox((register >> 120) as u8) as u128) << 120);
+        register = (register & !(0xFF << 120)) | top;
+        register = register.rotate_right(61);
+    }
+    register
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_80: u128 = 0x0123_4567_89AB_CDEF_FEDC;
+    const KEY_128: u128 = 0x0123_4567_89AB_CDEF_FEDC_BA98_7654_3210;
+
+    #[test]
+    fn known_answers_80() {
+        let ones = (1 << 80) - 1;
+        for (plaintext, key, ciphertext) in [
+            (0, 0, 0x5579_C138_7B22_8445),
+            (0, ones, 0xE72C_46C0_F594_5049),
+            (u64::MAX, 0, 0xA112_FFC7_2F68_417B),
+            (u64::MAX, ones, 0x3333_DCD3_2132_10D2),
+        ] {
+            assert_eq!(encrypt(plaintext, &key_schedule_80(key)), ciphertext);
+        }
+    }
+
+    #[test]
+    fn known_answers_128() {
+        for (plaintext, key, ciphertext) in [
+            (0, 0, 0x96DB_702A_2E69_00AF),
+            (0, u128::MAX, 0x1323_8C71_0272_A5D8),
+            (u64::MAX, 0, 0x3C60_19E5_E5ED_D563),
+            (u64::MAX, u128::MAX, 0x628D_9FBD_4218_E5B4),
+        ] {
+            assert_eq!(encrypt(plaintext, &key_schedule_128(key)), ciphertext);
+        }
+    }
+
+    #[test]
+    fn layers() {
+        for i in 0..16 {
+            assert_eq!(inv_sbox(sbox(i)), i);
+        }
+        /* Nibble i goes through the sbox, bit i moves to bit 16i mod 63 */
+        assert_eq!(s_layer(0x0000_0000_0000_00F0), 0xCCCC_CCCC_CCCC_CC2C);
+        assert_eq!(p_layer(1 << 1), 1 << 16);
+        assert_eq!(p_layer(1 << 4), 1 << 1);
+        assert_eq!(p_layer(1 << 63), 1 << 63);
+        /* The first round sbox output targeted by a CPA, nibble by nibble */
+        let (plaintext, round_key) = (0x0123_4567_89AB_CDEF, key_schedule_80(KEY_80)[0]);
+        let output = s_layer(plaintext ^ round_key);
+        for i in 0..16 {
+            let input = (plaintext >> (4 * i)) ^ (round_key >> (4 * i));
+            assert_eq!((output >> (4 * i)) & 0xF, sbox(input as u8) as u64);
+        }
+    }
+
+    #[test]
+    fn key_schedule_inversion() {
+        let round_keys = key_schedule_80(KEY_80);
+        for round in [1, 2, 16, ROUNDS] {
+            let key = invert_key_schedule_80(round_keys[round - 1], round_keys[round], round);
+            assert_eq!(key, KEY_80);
+        }
+        let round_keys = key_schedule_128(KEY_128);
+        for round in [1, 2, 16, ROUNDS - 1] {
+            let keys = [
+                round_keys[round - 1],
+                round_keys[round],
+                round_keys[round + 1],
+            ];
+            assert_eq!(invert_key_schedule_128(keys, round), KEY_128);
+        }
+    }
+}
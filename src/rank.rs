@@ -0,0 +1,128 @@
+use ndarray::{Array2, ArrayView2};
+use plotly::common::Title;
+use plotly::layout::Axis;
+use plotly::{Layout, Plot, Scatter};
+use std::fs::File;
+
+/* This class records, for a known correct key, its rank, its correlation and
+the best wrong-key correlation each time an engine reaches a checkpoint */
+
+#[derive(Clone, Debug)]
+pub struct RankTracker {
+    correct_key: usize,
+    traces: Vec<usize>,
+    ranks: Vec<usize>,
+    correct_corr: Vec<f32>,
+    best_wrong: Vec<f32>,
+}
+
+impl RankTracker {
+    pub fn new(correct_key: usize) -> Self {
+        Self {
+            correct_key,
+            traces: Vec::new(),
+            ranks: Vec::new(),
+            correct_corr: Vec::new(),
+            best_wrong: Vec::new(),
+        }
+    }
+
+    /// Records a checkpoint from the `guess_range x 1` max correlation array.
+    pub fn record(&mut self, traces_no: usize, max_corr: ArrayView2<f32>) {
+        let correct = max_corr[[self.correct_key, 0]];
+        let mut rank = 1;
+        let mut best_wrong: f32 = 0.0;
+        for (guess, value) in max_corr.column(0).iter().enumerate() {
+            if guess == self.correct_key {
+                continue;
+            }
+            if *value > correct {
+                rank += 1;
+            }
+            best_wrong = best_wrong.max(*value);
+        }
+        self.traces.push(traces_no);
+        self.ranks.push(rank);
+        self.correct_corr.push(correct);
+        self.best_wrong.push(best_wrong);
+    }
+
+    pub fn correct_key(&self) -> usize {
+        self.correct_key
+    }
+
+    /// Rank of the correct key at the last checkpoint (1 means recovered).
+    pub fn last_rank(&self) -> Option<usize> {
+        self.ranks.last().copied()
+    }
+
+    /// Number of traces from which the correct key stays ranked first.
+    pub fn traces_to_rank_one(&self) -> Option<usize> {
+        let stable_from = self.ranks.iter().rposition(|rank| *rank != 1);
+        match stable_from {
+            None => self.traces.first().copied(),
+            Some(i) => self.traces.get(i + 1).copied(),
+        }
+    }
+
+    /// One row per checkpoint: traces, rank, correct key correlation and
+    /// best wrong key correlation.
+    pub fn pass_table(&self) -> Array2<f32> {
+        let mut table = Array2::zeros((self.traces.len(), 4));
+        for i in 0..self.traces.len() {
+            table[[i, 0]] = self.traces[i] as f32;
+            table[[i, 1]] = self.ranks[i] as f32;
+            table[[i, 2]] = self.correct_corr[i];
+            table[[i, 3]] = self.best_wrong[i];
+        }
+        table
+    }
+
+    pub fn write_csv(&self, dir: &str) {
+        let mut writer = csv::Writer::from_writer(File::create(dir).unwrap());
+        writer
+            .write_record(["traces", "rank", "correct_corr", "best_wrong_corr"])
+            .unwrap();
+        for i in 0..self.traces.len() {
+            writer
+                .write_record([
+                    self.traces[i].to_string(),
+                    self.ranks[i].to_string(),
+                    self.correct_corr[i].to_string(),
+                    self.best_wrong[i].to_string(),
+                ])
+                .unwrap();
+        }
+        writer.flush().unwrap();
+    }
+
+    pub fn plot_rank(&self) -> Plot {
+        let mut plot = Plot::new();
+        let trace = Scatter::new(self.traces.clone(), self.ranks.clone())
+            .name(format!("rank of {}", self.correct_key));
+        plot.add_trace(trace);
+        plot.set_layout(
+            Layout::new()
+                .title(Title::from("Rank of the correct key"))
+                .x_axis(Axis::new().title(Title::from("traces")))
+                .y_axis(Axis::new().title(Title::from("rank"))),
+        );
+        plot
+    }
+
+    pub fn plot_corr(&self) -> Plot {
+        let mut plot = Plot::new();
+        let correct = Scatter::new(self.traces.clone(), self.correct_corr.clone())
+            .name(format!("K[{}]", self.correct_key));
+        let wrong = Scatter::new(self.traces.clone(), self.best_wrong.clone()).name("best wrong");
+        plot.add_trace(correct);
+        plot.add_trace(wrong);
+        plot.set_layout(
+            Layout::new()
+                .title(Title::from("Correlation of the correct key"))
+                .x_axis(Axis::new().title(Title::from("traces")))
+                .y_axis(Axis::new().title(Title::from("correlation"))),
+        );
+        plot
+    }
+}
@@ -0,0 +1,229 @@
+use crate::pipeline::Stage;
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+
+/* Outlier rejection: running per-sample statistics are accumulated over the
+accepted traces and every new trace is compared with the statistics of the
+previous batches. A trace is rejected when its largest per-sample z-score,
+its Mahalanobis distance on a set of points of interest or its number of
+clipped ADC samples exceeds the configured limit. The distance tests only
+start once `warmup` traces have been accepted; clipping is always checked.
+Rejected traces are dropped from the batch together with their metadata and
+recorded with their index in the stream of traces seen by the stage. */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reason {
+    /// Largest |z-score| and the sample where it occurs.
+    ZScore { sample: usize, z: f32 },
+    /// Mahalanobis distance on the points of interest.
+    Mahalanobis(f32),
+    /// Number of samples at or beyond the ADC limits.
+    Clipping(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rejection {
+    pub index: usize,
+    pub reason: Reason,
+}
+
+pub struct OutlierFilter {
+    count: usize,
+    sum: Array1<f64>,
+    sum2: Array1<f64>,
+    max_z: Option<f64>,
+    pois: Vec<usize>,
+    poi_sum: Array1<f64>,
+    poi_cross: Array2<f64>,
+    max_distance: Option<f64>,
+    clipping: Option<(f32, f32, usize)>,
+    warmup: usize,
+    seen: usize,
+    rejected: Vec<Rejection>,
+}
+
+impl OutlierFilter {
+    /// Filter for traces of `size` samples, the distance tests start after
+    /// `warmup` accepted traces.
+    pub fn new(size: usize, warmup: usize) -> Self {
+        Self {
+            count: 0,
+            sum: Array1::zeros(size),
+            sum2: Array1::zeros(size),
+            max_z: None,
+            pois: Vec::new(),
+            poi_sum: Array1::zeros(0),
+            poi_cross: Array2::zeros((0, 0)),
+            max_distance: None,
+            clipping: None,
+            warmup,
+            seen: 0,
+            rejected: Vec::new(),
+        }
+    }
+
+    /// Rejects traces with a sample further than `max_z` standard deviations
+    /// from the running mean.
+    pub fn z_score(&mut self, max_z: f64) {
+        self.max_z = Some(max_z);
+    }
+
+    /// Rejects traces whose Mahalanobis distance on the samples `pois`
+    /// exceeds `max_distance`.
+    pub fn mahalanobis(&mut self, pois: &[usize], max_distance: f64) {
+        self.pois = pois.to_vec();
+        self.poi_sum = Array1::zeros(pois.len());
+        self.poi_cross = Array2::zeros((pois.len(), pois.len()));
+        self.max_distance = Some(max_distance);
+    }
+
+    /// Rejects traces with more than `max_count` samples <= `low` or >= `high`.
+    pub fn clipping(&mut self, low: f32, high: f32, max_count: usize) {
+        self.clipping = Some((low, high, max_count));
+    }
+
+    pub fn rejected(&self) -> &[Rejection] {
+        &self.rejected
+    }
+
+    /// Number of traces accepted so far.
+    pub fn accepted(&self) -> usize {
+        self.count
+    }
+
+    fn accumulate(&mut self, trace: ArrayView1<f32>) {
+        self.count += 1;
+        for (i, x) in trace.iter().enumerate() {
+            let x = *x as f64;
+            self.sum[i] += x;
+            self.sum2[i] += x * x;
+        }
+        for (a, i) in self.pois.iter().enumerate() {
+            let x = trace[*i] as f64;
+            self.poi_sum[a] += x;
+            for (b, j) in self.pois.iter().enumerate() {
+                self.poi_cross[[a, b]] += x * trace[*j] as f64;
+            }
+        }
+    }
+
+    /// Mean and inverse covariance matrix on the points of interest, None when
+    /// the covariance matrix is singular.
+    fn poi_model(&self) -> Option<(Array1<f64>, Array2<f64>)> {
+        let n = self.count as f64;
+        let mean = &self.poi_sum / n;
+        let mut cov = &self.poi_cross / n;
+        for a in 0..mean.len() {
+            for b in 0..mean.len() {
+                cov[[a, b]] -= mean[a] * mean[b];
+            }
+        }
+        Some((mean, invert(cov)?))
+    }
+
+    fn check(
+        &self,
+        trace: ArrayView1<f32>,
+        moments: &Option<(Array1<f64>, Array1<f64>)>,
+        poi_model: &Option<(Array1<f64>, Array2<f64>)>,
+    ) -> Option<Reason> {
+        if let Some((low, high, max_count)) = self.clipping {
+            let clipped = trace.iter().filter(|x| **x <= low || **x >= high).count();
+            if clipped > max_count {
+                return Some(Reason::Clipping(clipped));
+            }
+        }
+        if let (Some(max_z), Some((mean, std))) = (self.max_z, moments) {
+            let (sample, z) = trace
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (i, ((*x as f64 - mean[i]) / std[i]).abs()))
+                .filter(|(_, z)| z.is_finite())
+                .fold((0, 0.0), |m, (i, z)| if z > m.1 { (i, z) } else { m });
+            if z > max_z {
+                return Some(Reason::ZScore {
+                    sample,
+                    z: z as f32,
+                });
+            }
+        }
+        if let (Some(max_distance), Some((mean, inv_cov))) = (self.max_distance, poi_model) {
+            let diff =
+                Array1::from_shape_fn(self.pois.len(), |a| trace[self.pois[a]] as f64 - mean[a]);
+            let distance = diff.dot(&inv_cov.dot(&diff)).max(0.0).sqrt();
+            if distance > max_distance {
+                return Some(Reason::Mahalanobis(distance as f32));
+            }
+        }
+        None
+    }
+}
+
+impl Stage for OutlierFilter {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        /* Traces are tested against the statistics before the batch */
+        let warm = self.count >= self.warmup.max(2);
+        let moments = warm.then(|| {
+            let mean = &self.sum / self.count as f64;
+            let std = (&self.sum2 / self.count as f64 - &mean * &mean).map(|v| v.max(0.0).sqrt());
+            (mean, std)
+        });
+        let poi_model = if warm && self.max_distance.is_some() {
+            self.poi_model()
+        } else {
+            None
+        };
+        let mut kept = Vec::new();
+        for (i, trace) in traces.axis_iter(Axis(0)).enumerate() {
+            match self.check(trace, &moments, &poi_model) {
+                Some(reason) => self.rejected.push(Rejection {
+                    index: self.seen + i,
+                    reason,
+                }),
+                None => kept.push(i),
+            }
+        }
+        self.seen += traces.shape()[0];
+        for i in kept.iter() {
+            self.accumulate(traces.row(*i));
+        }
+        (
+            traces.select(Axis(0), &kept),
+            metadata.select(Axis(0), &kept),
+        )
+    }
+}
+
+/// Gauss-Jordan inversion with partial pivoting.
+fn invert(mut a: Array2<f64>) -> Option<Array2<f64>> {
+    let n = a.shape()[0];
+    let mut inv = Array2::eye(n);
+    for col in 0..n {
+        let pivot = (col..n).max_by(|i, j| a[[*i, col]].abs().total_cmp(&a[[*j, col]].abs()))?;
+        if a[[pivot, col]].abs() < 1e-12 {
+            return None;
+        }
+        for k in 0..n {
+            a.swap([col, k], [pivot, k]);
+            inv.swap([col, k], [pivot, k]);
+        }
+        let p = a[[col, col]];
+        for k in 0..n {
+            a[[col, k]] /= p;
+            inv[[col, k]] /= p;
+        }
+        for row in 0..n {
+            if row != col {
+                let f = a[[row, col]];
+                for k in 0..n {
+                    a[[row, k]] -= f * a[[col, k]];
+                    inv[[row, k]] -= f * inv[[col, k]];
+                }
+            }
+        }
+    }
+    Some(inv)
+}
@@ -0,0 +1,141 @@
+use crate::reject::Rejection;
+use crate::stats::Significance;
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2, Axis};
+
+/* This class holds the outcome of finalize: every guess sorted by its
+maximum correlation, the sample where each guess peaks, the statistical
+confidence of the peaks and optionally the traces rejected before the
+attack. Guesses are absolute, i.e. include the guess offset of a tiled
+instance. */
+
+#[derive(Clone, Debug)]
+pub struct CpaResult {
+    ranking: Vec<usize>,
+    scores: Array1<f32>,
+    peaks: Array1<usize>,
+    guess_offset: usize,
+    significance: Significance,
+    rejected: Vec<Rejection>,
+}
+
+impl CpaResult {
+    /// Builds the result from a `guess_range x len_samples` correlation array.
+    pub fn new(corr: ArrayView2<f32>, traces_no: usize, guess_offset: usize) -> Self {
+        let mut scores = Array1::zeros(corr.shape()[0]);
+        let mut peaks = Array1::zeros(corr.shape()[0]);
+        for (i, row) in corr.axis_iter(Axis(0)).enumerate() {
+            for (x, value) in row.iter().enumerate() {
+                if *value > scores[i] {
+                    scores[i] = *value;
+                    peaks[i] = x;
+                }
+            }
+        }
+        Self::from_maxima(scores, peaks, traces_no, guess_offset)
+    }
+
+    /// Builds the result from per-guess maxima and their sample indices.
+    pub fn from_maxima(
+        scores: Array1<f32>,
+        peaks: Array1<usize>,
+        traces_no: usize,
+        guess_offset: usize,
+    ) -> Self {
+        let mut ranking: Vec<usize> = (0..scores.len()).collect();
+        /* NaN scores (constant samples) are ranked last */
+        let key = |i: &usize| {
+            if scores[*i].is_nan() {
+                f32::NEG_INFINITY
+            } else {
+                scores[*i]
+            }
+        };
+        ranking.sort_by(|a, b| key(b).total_cmp(&key(a)));
+        let max_corr: Array2<f32> = scores.clone().insert_axis(Axis(1));
+        Self {
+            ranking: ranking.into_iter().map(|i| i + guess_offset).collect(),
+            significance: Significance::new(max_corr.view(), traces_no),
+            scores,
+            peaks,
+            guess_offset,
+            rejected: Vec::new(),
+        }
+    }
+
+    /// Best guess, or None when no guess has a positive correlation.
+    pub fn best(&self) -> Option<usize> {
+        let best = *self.ranking.first()?;
+        if self.score(best) > 0.0 {
+            Some(best)
+        } else {
+            None
+        }
+    }
+
+    /// All guesses sorted by decreasing score.
+    pub fn ranking(&self) -> &[usize] {
+        &self.ranking
+    }
+
+    pub fn top_k(&self, k: usize) -> &[usize] {
+        &self.ranking[..k.min(self.ranking.len())]
+    }
+
+    /// Rank of `guess`, 1 being the best guess, None when `guess` is not
+    /// covered by this result.
+    pub fn rank_of(&self, guess: usize) -> Option<usize> {
+        self.ranking
+            .iter()
+            .position(|g| *g == guess)
+            .map(|rank| rank + 1)
+    }
+
+    /// Maximum correlation of `guess` over the samples.
+    pub fn score(&self, guess: usize) -> f32 {
+        self.scores[self.index(guess)]
+    }
+
+    /// Sample index where `guess` reaches its maximum correlation.
+    pub fn peak(&self, guess: usize) -> usize {
+        self.peaks[self.index(guess)]
+    }
+
+    /// Position of `guess` in the per-guess arrays.
+    fn index(&self, guess: usize) -> usize {
+        let range = self.guess_offset..self.guess_offset + self.scores.len();
+        assert!(
+            range.contains(&guess),
+            "guess {guess} is outside the guesses {range:?} of this result"
+        );
+        guess - self.guess_offset
+    }
+
+    /// Score difference between each ranked guess and the next one.
+    pub fn margins(&self) -> Vec<f32> {
+        self.ranking
+            .windows(2)
+            .map(|pair| self.score(pair[0]) - self.score(pair[1]))
+            .collect()
+    }
+
+    pub fn pass_scores(&self) -> ArrayView1<'_, f32> {
+        self.scores.view()
+    }
+
+    pub fn pass_peaks(&self) -> ArrayView1<'_, usize> {
+        self.peaks.view()
+    }
+
+    pub fn significance(&self) -> &Significance {
+        &self.significance
+    }
+
+    /// Records the traces rejected before the attack, e.g. by an `OutlierFilter`.
+    pub fn rejected_traces(&mut self, rejected: &[Rejection]) {
+        self.rejected.extend_from_slice(rejected);
+    }
+
+    pub fn rejected(&self) -> &[Rejection] {
+        &self.rejected
+    }
+}
@@ -0,0 +1,237 @@
+/* SKINNY-64/128 primitives used to build leakage models, as specified in:
+https://eprint.iacr.org/2016/660.pdf
+The state is handled as 16 cells in row-major order, cell 0 being the first
+nibble (SKINNY-64) or byte (SKINNY-128) of the block. Only the TK1 tweakey
+schedule is covered. The round tweakey is added after the sbox, so a CPA
+targets the sbox of the second round: MixColumns copies row 0 into row 1
+and xors row 1 into row 2, which isolates each of the 8 round tweakey cells. */
+
+pub const SBOX_4: [u8; 16] = [
+    0xC, 0x6, 0x9, 0x0, 0x1, 0xA, 0x2, 0xB, 0x3, 0x8, 0x5, 0xD, 0x4, 0xE, 0x7, 0xF,
+];
+
+pub const SBOX_8: [u8; 256] = [
+    0x65, 0x4C, 0x6A, 0x42, 0x4B, 0x63, 0x43, 0x6B, 0x55, 0x75, 0x5A, 0x7A, 0x53, 0x73, 0x5B, 0x7B,
+    0x35, 0x8C, 0x3A, 0x81, 0x89, 0x33, 0x80, 0x3B, 0x95, 0x25, 0x98, 0x2A, 0x90, 0x23, 0x99, 0x2B,
+    0xE5, 0xCC, 0xE8, 0xC1, 0xC9, 0xE0, 0xC0, 0xE9, 0xD5, 0xF5, 0xD8, 0xF8, 0xD0, 0xF0, 0xD9, 0xF9,
+    0xA5, 0x1C, 0xA8, 0x12, 0x1B, 0xA0, 0x13, 0xA9, 0x05, 0xB5, 0x0A, 0xB8, 0x03, 0xB0, 0x0B, 0xB9,
+    0x32, 0x88, 0x3C, 0x85, 0x8D, 0x34, 0x84, 0x3D, 0x91, 0x22, 0x9C, 0x2C, 0x94, 0x24, 0x9D, 0x2D,
+    0x62, 0x4A, 0x6C, 0x45, 0x4D, 0x64, 0x44, 0x6D, 0x52, 0x72, 0x5C, 0x7C, 0x54, 0x74, 0x5D, 0x7D,
+    0xA1, 0x1A, 0xAC, 0x15, 0x1D, 0xA4, 0x14, 0xAD, 0x02, 0xB1, 0x0C, 0xBC, 0x04, 0xB4, 0x0D, 0xBD,
+    0xE1, 0xC8, 0xEC, 0xC5, 0xCD, 0xE4, 0xC4, 0xED, 0xD1, 0xF1, 0xDC, 0xFC, 0xD4, 0xF4, 0xDD, 0xFD,
+    0x36, 0x8E, 0x38, 0x82, 0x8B, 0x30, 0x83, 0x39, 0x96, 0x26, 0x9A, 0x28, 0x93, 0x20, 0x9B, 0x29,
+    0x66, 0x4E, 0x68, 0x41, 0x49, 0x60, 0x40, 0x69, 0x56, 0x76, 0x58, 0x78, 0x50, 0x70, 0x59, 0x79,
+    0xA6, 0x1E, 0xAA, 0x11, 0x19, 0xA3, 0x10, 0xAB, 0x06, 0xB6, 0x08, 0xBA, 0x00, 0xB3, 0x09, 0xBB,
+    0xE6, 0xCE, 0xEA, 0xC2, 0xCB, 0xE3, 0xC3, 0xEB, 0xD6, 0xF6, 0xDA, 0xFA, 0xD3, 0xF3, 0xDB, 0xFB,
+    0x31, 0x8A, 0x3E, 0x86, 0x8F, 0x37, 0x87, 0x3F, 0x92, 0x21, 0x9E, 0x2E, 0x97, 0x27, 0x9F, 0x2F,
+    0x61, 0x48, 0x6E, 0x46, 0x4F, 0x67, 0x47, 0x6F, 0x51, 0x71, 0x5E, 0x7E, 0x57, 0x77, 0x5F, 0x7F,
+    0xA2, 0x18, 0xAE, 0x16, 0x1F, 0xA7, 0x17, 0xAF, 0x01, 0xB2, 0x0E, 0xBE, 0x07, 0xB7, 0x0F, 0xBF,
+    0xE2, 0xCA, 0xEE, 0xC6, 0xCF, 0xE7, 0xC7, 0xEF, 0xD2, 0xF2, 0xDE, 0xFE, 0xD7, 0xF7, 0xDF, 0xFF,
+];
+
+/// Tweakey cell permutation applied between rounds.
+pub const PT: [usize; 16] = [9, 15, 8, 13, 10, 14, 12, 11, 0, 1, 2, 3, 4, 5, 6, 7];
+
+pub fn sbox_4(index: u8) -> u8 {
+    SBOX_4[(index & 0xF) as usize]
+}
+
+pub fn sbox_8(index: u8) -> u8 {
+    SBOX_8[index as usize]
+}
+
+/// Splits a 64-bit block (most significant nibble first) into cells.
+pub fn cells_64(block: u64) -> [u8; 16] {
+    let mut cells = [0; 16];
+    for (i, cell) in cells.iter_mut().enumerate() {
+        *cell = ((block >> (60 - 4 * i)) & 0xF) as u8;
+    }
+    cells
+}
+
+pub fn block_64(cells: &[u8; 16]) -> u64 {
+    cells
+        .iter()
+        .fold(0, |acc, cell| (acc << 4) | (*cell & 0xF) as u64)
+}
+
+/// Round constant of round `round` (starting at 1).
+pub fn round_constant(round: usize) -> u8 {
+    let mut constant: u8 = 0;
+    for _ in 0..round {
+        let feedback = ((constant >> 5) ^ (constant >> 4) ^ 1) & 1;
+        constant = ((constant << 1) | feedback) & 0x3F;
+    }
+    constant
+}
+
+/// Constants added to cells 0, 4 and 8 in round `round`.
+pub fn cell_constant(cell: usize, round: usize) -> u8 {
+    let constant = round_constant(round);
+    match cell {
+        0 => constant & 0xF,
+        4 => constant >> 4,
+        8 => 0x2,
+        _ => 0,
+    }
+}
+
+/// Round function with the sbox given as a parameter (`sbox_4` or `sbox_8`).
+pub fn round(state: &[u8; 16], round_key: &[u8; 8], round: usize, sbox: fn(u8) -> u8) -> [u8; 16] {
+    let mut cells = [0; 16];
+    for i in 0..16 {
+        cells[i] = sbox(state[i]) ^ cell_constant(i, round);
+        if i < 8 {
+            cells[i] ^= round_key[i];
+        }
+    }
+    /* ShiftRows rotates row r to the right by r cells */
+    let mut shifted = [0; 16];
+    for row in 0..4 {
+        for column in 0..4 {
+            shifted[4 * row + column] = cells[4 * row + (column + 4 - row) % 4];
+        }
+    }
+    let mut out = [0; 16];
+    for column in 0..4 {
+        let r: Vec<u8> = (0..4).map(|row| shifted[4 * row + column]).collect();
+        out[column] = r[0] ^ r[2] ^ r[3];
+        out[4 + column] = r[0];
+        out[8 + column] = r[1] ^ r[2];
+        out[12 + column] = r[0] ^ r[2];
+    }
+    out
+}
+
+/// Input of the second round sbox in which the round tweakey cell `cell`
+/// (0..8) appears alone, given the plaintext cells and a guess of that cell.
+pub fn second_round_input(plaintext: &[u8; 16], cell: usize, guess: u8, sbox: fn(u8) -> u8) -> u8 {
+    let keyed = sbox(plaintext[cell]) ^ cell_constant(cell, 1) ^ guess;
+    if cell < 4 {
+        keyed
+    } else {
+        /* Row 1 was shifted by one, row 2 by two before being xored */
+        let column = (cell - 4 + 1) % 4;
+        let other = 8 + (column + 2) % 4;
+        keyed ^ sbox(plaintext[other]) ^ cell_constant(other, 1)
+    }
+}
+
+/// Combines the 8 cell guesses into a round tweakey.
+pub fn combine_guesses(guesses: &[i32]) -> [u8; 8] {
+    let mut round_key = [0; 8];
+    for (cell, guess) in round_key.iter_mut().zip(guesses) {
+        *cell = *guess as u8;
+    }
+    round_key
+}
+
+/// Round tweakeys (first two rows of TK1) of the first `rounds` rounds.
+pub fn tk1_schedule(tweakey: &[u8; 16], rounds: usize) -> Vec<[u8; 8]> {
+    let mut state = *tweakey;
+    let mut round_keys = Vec::with_capacity(rounds);
+    for _ in 0..rounds {
+        let mut round_key = [0; 8];
+        round_key.copy_from_slice(&state[..8]);
+        round_keys.push(round_key);
+        state = PT.map(|p| state[p]);
+    }
+    round_keys
+}
+
+pub fn encrypt(plaintext: &[u8; 16], round_keys: &[[u8; 8]], sbox: fn(u8) -> u8) -> [u8; 16] {
+    let mut state = *plaintext;
+    for (r, round_key) in round_keys.iter().enumerate() {
+        state = round(&state, round_key, r + 1, sbox);
+    }
+    state
+}
+
+/// Recovers TK1 from the round tweakeys of rounds `round` and `round + 1`.
+pub fn invert_tk1_schedule(round_keys: [[u8; 8]; 2], round: usize) -> [u8; 16] {
+    let mut state = [0; 16];
+    state[..8].copy_from_slice(&round_keys[0]);
+    for (i, cell) in round_keys[1].iter().enumerate() {
+        state[PT[i]] = *cell;
+    }
+    for _ in 1..round {
+        let mut previous = [0; 16];
+        for i in 0..16 {
+            previous[PT[i]] = state[i];
+        }
+        state = previous;
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells_128(block: u128) -> [u8; 16] {
+        block.to_be_bytes()
+    }
+
+    #[test]
+    fn known_answers() {
+        /* SKINNY-64-64 and SKINNY-128-128, which only use TK1 */
+        let round_keys = tk1_schedule(&cells_64(0xF526_9826_FC68_1238), 32);
+        let ciphertext = encrypt(&cells_64(0x0603_4F95_7724_D19D), &round_keys, sbox_4);
+        assert_eq!(block_64(&ciphertext), 0xBB39_DFB2_429B_8AC7);
+
+        let tweakey = cells_128(0x4F55_CFB0_520C_AC52_FD92_C15F_3707_3E93);
+        let plaintext = cells_128(0xF20A_DB0E_B08B_648A_3B2E_EED1_F0AD_DA14);
+        let ciphertext = encrypt(&plaintext, &tk1_schedule(&tweakey, 40), sbox_8);
+        assert_eq!(
+            u128::from_be_bytes(ciphertext),
+            0x22FF_30D4_98EA_62D7_E45B_476E_3367_5B74
+        );
+    }
+
+    #[test]
+    fn round_constants() {
+        /* First constants listed in the specification */
+        let constants: Vec<u8> = (1..=8).map(round_constant).collect();
+        assert_eq!(constants, [0x01, 0x03, 0x07, 0x0F, 0x1F, 0x3E, 0x3D, 0x3B]);
+    }
+
+    /// Every round tweakey cell appears alone in a second round sbox input.
+    #[test]
+    fn second_round_inputs() {
+        for (sbox, plaintext, tweakey) in [
+            (
+                sbox_4 as fn(u8) -> u8,
+                cells_64(0x0603_4F95_7724_D19D),
+                cells_64(0xF526_9826_FC68_1238),
+            ),
+            (
+                sbox_8,
+                cells_128(0xF20A_DB0E_B08B_648A_3B2E_EED1_F0AD_DA14),
+                cells_128(0x4F55_CFB0_520C_AC52_FD92_C15F_3707_3E93),
+            ),
+        ] {
+            let round_key = tk1_schedule(&tweakey, 1)[0];
+            assert_eq!(combine_guesses(&round_key.map(|c| c as i32)), round_key);
+            let state = round(&plaintext, &round_key, 1, sbox);
+            for (cell, guess) in round_key.iter().enumerate() {
+                let position = if cell < 4 {
+                    4 + cell
+                } else {
+                    8 + (cell - 4 + 1) % 4
+                };
+                let input = second_round_input(&plaintext, cell, *guess, sbox);
+                assert_eq!(input, state[position]);
+            }
+        }
+    }
+
+    #[test]
+    fn tk1_schedule_inversion() {
+        let tweakey = cells_128(0x4F55_CFB0_520C_AC52_FD92_C15F_3707_3E93);
+        let round_keys = tk1_schedule(&tweakey, 40);
+        for round in [1, 2, 17, 39] {
+            let keys = [round_keys[round - 1], round_keys[round]];
+            assert_eq!(invert_tk1_schedule(keys, round), tweakey);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::compress::Reduction;
+use crate::pipeline::{map_rows, Stage};
+use ndarray::{s, Array1, Array2, ArrayView1};
+
+/* Countermeasures spreading the leakage in time: with shuffling the target
+operation runs in one of several time slots, with random delays it moves
+around its nominal position. Both stages combine the candidate samples of
+every trace so that the leakage of the target lands on the same output
+sample whatever the slot or delay, at the cost of a lower correlation. */
+
+/// Combines the samples at the same position within every slot: the output
+/// sample j is the reduction of trace[offset + j] over the slot offsets, for
+/// j in 0..len_slot.
+#[derive(Clone, Debug)]
+pub struct SlotCombine {
+    offsets: Vec<usize>,
+    len_slot: usize,
+    reduction: Reduction,
+}
+
+impl SlotCombine {
+    pub fn new(offsets: &[usize], len_slot: usize, reduction: Reduction) -> Self {
+        assert!(!offsets.is_empty(), "at least one slot is needed");
+        Self {
+            offsets: offsets.to_vec(),
+            len_slot,
+            reduction,
+        }
+    }
+
+    /// `slots` slots of `period` samples, the first one starting at `first`.
+    pub fn periodic(first: usize, period: usize, slots: usize, reduction: Reduction) -> Self {
+        let offsets: Vec<usize> = (0..slots).map(|k| first + k * period).collect();
+        Self::new(&offsets, period, reduction)
+    }
+
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    fn check_slots(&self, len_in: usize) {
+        let end = self.offsets.iter().max().unwrap() + self.len_slot;
+        assert!(
+            end <= len_in,
+            "the slots end at sample {end}, past the {len_in} samples of the traces"
+        );
+    }
+
+    pub fn apply(&self, trace: ArrayView1<f32>) -> Array1<f32> {
+        self.check_slots(trace.len());
+        let mut candidates = vec![0.0; self.offsets.len()];
+        Array1::from_shape_fn(self.len_slot, |j| {
+            for (c, offset) in candidates.iter_mut().zip(&self.offsets) {
+                *c = trace[offset + j];
+            }
+            self.reduction.reduce(ArrayView1::from(&candidates))
+        })
+    }
+}
+
+impl Stage for SlotCombine {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        let len_out = self.len_samples(traces.shape()[1]);
+        (
+            map_rows(traces.view(), len_out, |t| self.apply(t)),
+            metadata,
+        )
+    }
+
+    fn len_samples(&self, len_in: usize) -> usize {
+        self.check_slots(len_in);
+        self.len_slot
+    }
+}
+
+/// Sliding-window integration: the output sample i is the reduction of
+/// trace[i..i + len_window], which absorbs delays up to len_window samples.
+#[derive(Clone, Copy, Debug)]
+pub struct SlidingWindow {
+    len_window: usize,
+    reduction: Reduction,
+}
+
+impl SlidingWindow {
+    pub fn new(len_window: usize, reduction: Reduction) -> Self {
+        assert!(len_window > 0, "the sliding window must not be empty");
+        Self {
+            len_window,
+            reduction,
+        }
+    }
+
+    pub fn apply(&self, trace: ArrayView1<f32>) -> Array1<f32> {
+        Array1::from_shape_fn(self.len_samples(trace.len()), |i| {
+            self.reduction
+                .reduce(trace.slice(s![i..i + self.len_window]))
+        })
+    }
+}
+
+impl Stage for SlidingWindow {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        let len_out = self.len_samples(traces.shape()[1]);
+        (
+            map_rows(traces.view(), len_out, |t| self.apply(t)),
+            metadata,
+        )
+    }
+
+    fn len_samples(&self, len_in: usize) -> usize {
+        (len_in + 1).saturating_sub(self.len_window)
+    }
+}
@@ -0,0 +1,145 @@
+use crate::fft::real_fft;
+use crate::pipeline::{map_rows, Stage};
+use ndarray::{Array1, Array2, ArrayView1};
+use std::f64::consts::PI;
+
+/* Frequency-domain preprocessing: every trace is cut into windows of
+len_window samples taken every step samples, each window is tapered and
+transformed into its one-sided magnitude (or power) spectrum. The spectra of
+the successive windows are concatenated, so an engine fed with the output
+attacks (window, frequency bin) pairs; a window as long as the trace gives a
+single spectrum per trace. The FFT is zero-padded to the next power of two. */
+
+#[derive(Clone, Copy, Debug)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl Window {
+    pub fn coefficients(&self, len: usize) -> Vec<f64> {
+        let denom = len.saturating_sub(1).max(1) as f64;
+        (0..len)
+            .map(|n| {
+                let phase = 2.0 * PI * n as f64 / denom;
+                match self {
+                    Window::Rectangular => 1.0,
+                    Window::Hann => 0.5 - 0.5 * phase.cos(),
+                    Window::Hamming => 0.54 - 0.46 * phase.cos(),
+                    Window::Blackman => 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Spectrum {
+    coefficients: Vec<f64>,
+    step: usize,
+    len_fft: usize,
+    power: bool,
+}
+
+impl Spectrum {
+    /// Spectra over windows of `len_window` samples, by default the windows
+    /// do not overlap.
+    pub fn new(len_window: usize, window: Window) -> Self {
+        assert!(len_window > 0, "the spectrum window must not be empty");
+        let coefficients = window.coefficients(len_window);
+        /* Amplitude normalisation: a sine of amplitude a gives a peak of a / 2 */
+        let gain: f64 = coefficients.iter().sum();
+        Self {
+            coefficients: coefficients.iter().map(|c| c / gain).collect(),
+            step: len_window,
+            len_fft: len_window.next_power_of_two(),
+            power: false,
+        }
+    }
+
+    /// Distance in samples between the starts of two consecutive windows.
+    pub fn step(&mut self, step: usize) {
+        assert!(step > 0, "the spectrum step must be at least 1");
+        self.step = step;
+    }
+
+    /// Outputs the squared magnitude instead of the magnitude.
+    pub fn power(&mut self, power: bool) {
+        self.power = power;
+    }
+
+    /// Zero-pads every window to `len_fft` points (rounded up to a power of two)
+    /// to interpolate the spectrum.
+    pub fn len_fft(&mut self, len_fft: usize) {
+        self.len_fft = len_fft.max(self.coefficients.len()).next_power_of_two();
+    }
+
+    pub fn len_bins(&self) -> usize {
+        self.len_fft / 2 + 1
+    }
+
+    pub fn len_windows(&self, len_in: usize) -> usize {
+        if len_in < self.coefficients.len() {
+            0
+        } else {
+            (len_in - self.coefficients.len()) / self.step + 1
+        }
+    }
+
+    /// Window start (in samples of the input trace) and frequency bin of the
+    /// output sample `index`.
+    pub fn locate(&self, index: usize) -> (usize, usize) {
+        (index / self.len_bins() * self.step, index % self.len_bins())
+    }
+
+    /// Frequency of the output sample `index` for a sampling rate of
+    /// `sample_rate`.
+    pub fn frequency(&self, index: usize, sample_rate: f64) -> f64 {
+        let (_, bin) = self.locate(index);
+        bin as f64 * sample_rate / self.len_fft as f64
+    }
+
+    pub fn apply(&self, trace: ArrayView1<f32>) -> Array1<f32> {
+        let len_bins = self.len_bins();
+        let mut out = Array1::zeros(self.len_windows(trace.len()) * len_bins);
+        for w in 0..self.len_windows(trace.len()) {
+            let start = w * self.step;
+            let windowed: Vec<f64> = self
+                .coefficients
+                .iter()
+                .enumerate()
+                .map(|(i, c)| c * trace[start + i] as f64)
+                .collect();
+            let spectrum = real_fft(&windowed, self.len_fft);
+            for (bin, value) in spectrum[..len_bins].iter().enumerate() {
+                let power = value.norm_sqr();
+                out[w * len_bins + bin] = if self.power {
+                    power as f32
+                } else {
+                    power.sqrt() as f32
+                };
+            }
+        }
+        out
+    }
+}
+
+impl Stage for Spectrum {
+    fn process(
+        &mut self,
+        traces: Array2<f32>,
+        metadata: Array2<usize>,
+    ) -> (Array2<f32>, Array2<usize>) {
+        let len_out = self.len_samples(traces.shape()[1]);
+        (
+            map_rows(traces.view(), len_out, |t| self.apply(t)),
+            metadata,
+        )
+    }
+
+    fn len_samples(&self, len_in: usize) -> usize {
+        self.len_windows(len_in) * self.len_bins()
+    }
+}
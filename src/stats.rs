@@ -0,0 +1,158 @@
+use ndarray::{Array1, ArrayView2};
+
+/* Statistics on correlation coefficients */
+
+/// Fisher z-transform of a correlation coefficient.
+pub fn fisher_z(corr: f64) -> f64 {
+    let corr = corr.clamp(-0.999_999_9, 0.999_999_9);
+    0.5 * ((1.0 + corr) / (1.0 - corr)).ln()
+}
+
+/// Complementary error function (Numerical Recipes erfcc, |error| < 1.2e-7).
+pub fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let r = t * poly.exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+/// Cumulative distribution function of the standard normal distribution.
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / std::f64::consts::SQRT_2)
+}
+
+/// One-sided p-value that the correlation `first` is not greater than
+/// `second`, both estimated from `traces_no` traces.
+pub fn difference_p_value(first: f64, second: f64, traces_no: usize) -> f64 {
+    if traces_no <= 3 {
+        return 1.0;
+    }
+    let z = (fisher_z(first) - fisher_z(second)) / (2.0 / (traces_no as f64 - 3.0)).sqrt();
+    1.0 - normal_cdf(z)
+}
+
+/// Quantile function of the standard normal distribution (Acklam's
+/// rational approximation, relative error < 1.2e-9).
+pub fn inverse_normal_cdf(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    let p_low = 0.02425;
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+    if p < p_low {
+        let q = (-2.0 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    } else if p <= 1.0 - p_low {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    } else {
+        -inverse_normal_cdf(1.0 - p)
+    }
+}
+
+/* This class reports the statistical confidence of the per-guess maximum
+correlations returned by finalize, given the number of traces they were
+estimated from */
+
+#[derive(Clone, Debug)]
+pub struct Significance {
+    max_corr: Array1<f32>,
+    traces_no: usize,
+}
+
+impl Significance {
+    pub fn new(max_corr: ArrayView2<f32>, traces_no: usize) -> Self {
+        Self {
+            max_corr: max_corr.column(0).to_owned(),
+            traces_no,
+        }
+    }
+
+    pub fn traces_no(&self) -> usize {
+        self.traces_no
+    }
+
+    fn standard_error(&self) -> f64 {
+        1.0 / (self.traces_no.max(4) as f64 - 3.0).sqrt()
+    }
+
+    /// Fisher z confidence interval (lower, upper) of each guess at `level`
+    /// (e.g. 0.95).
+    pub fn interval(&self, level: f64) -> (Array1<f32>, Array1<f32>) {
+        let spread = inverse_normal_cdf(0.5 + level / 2.0) * self.standard_error();
+        let lower = self
+            .max_corr
+            .map(|c| (fisher_z(*c as f64) - spread).tanh() as f32);
+        let upper = self
+            .max_corr
+            .map(|c| (fisher_z(*c as f64) + spread).tanh() as f32);
+        (lower, upper)
+    }
+
+    /// Two-sided p-value of each guess against the null of zero correlation.
+    pub fn p_values(&self) -> Array1<f64> {
+        let se = self.standard_error();
+        self.max_corr
+            .map(|c| erfc(fisher_z(*c as f64).abs() / se / std::f64::consts::SQRT_2).min(1.0))
+    }
+
+    /// Number of traces needed to detect the observed correlation of each
+    /// guess at `level` (e.g. 0.9999), using N = 3 + 8 (z / ln((1+r)/(1-r)))^2.
+    pub fn min_traces(&self, level: f64) -> Array1<f64> {
+        let z = inverse_normal_cdf(level);
+        self.max_corr.map(|c| {
+            let l = (2.0 * fisher_z(*c as f64)).abs();
+            if l == 0.0 {
+                f64::INFINITY
+            } else {
+                3.0 + 8.0 * (z / l).powi(2)
+            }
+        })
+    }
+}
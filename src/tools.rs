@@ -29,7 +29,7 @@ pub fn progress_bar(len: usize) -> ProgressBar {
 
 pub fn plot_array2(arr: Array2<f32>, name: String, t: String) -> Plot {
     let mut plot: Plot = Plot::new();
-    let x: Vec<f32> = (0..arr.shape()[1]).into_iter().map(|x| x as f32).collect();
+    let x: Vec<f32> = (0..arr.shape()[1]).map(|x| x as f32).collect();
     for i in 0..arr.shape()[0] {
         let trace = arr.row(i).to_vec();
         // let trace = Scatter::new(x.clone(), trace).name(format!("{name} [{i}]"));
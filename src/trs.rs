@@ -0,0 +1,546 @@
+use crate::dataset::{MetadataSource, TraceSource};
+use memmap2::Mmap;
+use ndarray::{Array2, ArrayView2};
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+/* Riscure Inspector trace sets (.trs). The file starts with a header made of
+tag, length, value objects and closed by the trace block tag; every trace
+then holds an optional title, the per-trace data bytes (plaintext,
+ciphertext, ...) and the samples. The reader maps the file and returns
+batches of traces as f32 samples (multiplied by the y scale) with the data
+bytes as metadata, like the NPY dataset. */
+
+const TAG_TRACES_NO: u8 = 0x41;
+const TAG_LEN_SAMPLES: u8 = 0x42;
+const TAG_CODING: u8 = 0x43;
+const TAG_DATA_LEN: u8 = 0x44;
+const TAG_TITLE_LEN: u8 = 0x45;
+const TAG_GLOBAL_TITLE: u8 = 0x46;
+const TAG_DESCRIPTION: u8 = 0x47;
+const TAG_X_SCALE: u8 = 0x4B;
+const TAG_Y_SCALE: u8 = 0x4C;
+const TAG_TRACE_BLOCK: u8 = 0x5F;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleCoding {
+    Int8,
+    Int16,
+    Int32,
+    Float32,
+}
+
+impl SampleCoding {
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            0x01 => Ok(SampleCoding::Int8),
+            0x02 => Ok(SampleCoding::Int16),
+            0x04 => Ok(SampleCoding::Int32),
+            0x14 => Ok(SampleCoding::Float32),
+            _ => Err(invalid(format!("unknown TRS sample coding {byte:#x}"))),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            SampleCoding::Int8 => 0x01,
+            SampleCoding::Int16 => 0x02,
+            SampleCoding::Int32 => 0x04,
+            SampleCoding::Float32 => 0x14,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            SampleCoding::Int8 => 1,
+            SampleCoding::Int16 => 2,
+            SampleCoding::Int32 | SampleCoding::Float32 => 4,
+        }
+    }
+
+    /// Largest value of the integer codings, None for floats.
+    pub fn max_value(&self) -> Option<f32> {
+        match self {
+            SampleCoding::Int8 => Some(i8::MAX as f32),
+            SampleCoding::Int16 => Some(i16::MAX as f32),
+            SampleCoding::Int32 => Some(i32::MAX as f32),
+            SampleCoding::Float32 => None,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleCoding::Int8 => bytes[0] as i8 as f32,
+            SampleCoding::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+            SampleCoding::Int32 => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32
+            }
+            SampleCoding::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+
+    /// Encodes `value`. The integer codings only take integer values within
+    /// their range, unless `round` is set, in which case the value is rounded
+    /// to the nearest integer first.
+    fn encode(&self, value: f32, round: bool, out: &mut Vec<u8>) -> Result<()> {
+        macro_rules! encode_int {
+            ($t:ty) => {{
+                let rounded = if round { value.round() } else { value };
+                if rounded.fract() != 0.0
+                    || (rounded as f64) < <$t>::MIN as f64
+                    || (rounded as f64) > <$t>::MAX as f64
+                {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{value} does not fit in {}", stringify!($t)),
+                    ));
+                }
+                out.extend((rounded as $t).to_le_bytes())
+            }};
+        }
+        match self {
+            SampleCoding::Int8 => encode_int!(i8),
+            SampleCoding::Int16 => encode_int!(i16),
+            SampleCoding::Int32 => encode_int!(i32),
+            SampleCoding::Float32 => out.extend(value.to_le_bytes()),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrsHeader {
+    pub traces_no: usize,
+    pub len_samples: usize,
+    pub coding: SampleCoding,
+    /// Number of data bytes per trace.
+    pub data_len: usize,
+    /// Number of title bytes per trace.
+    pub title_len: usize,
+    pub x_scale: f32,
+    pub y_scale: f32,
+    pub global_title: String,
+    pub description: String,
+    /// Offset of the first trace in the file.
+    pub header_len: usize,
+}
+
+impl TrsHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut header = Self {
+            traces_no: 0,
+            len_samples: 0,
+            coding: SampleCoding::Float32,
+            data_len: 0,
+            title_len: 0,
+            x_scale: 1.0,
+            y_scale: 1.0,
+            global_title: String::new(),
+            description: String::new(),
+            header_len: 0,
+        };
+        let mut pos = 0;
+        loop {
+            let tag = *bytes
+                .get(pos)
+                .ok_or_else(|| invalid("truncated TRS header"))?;
+            let mut len = *bytes
+                .get(pos + 1)
+                .ok_or_else(|| invalid("truncated TRS header"))? as usize;
+            pos += 2;
+            if len & 0x80 != 0 {
+                /* Long form: the low bits give the number of length bytes */
+                let len_bytes = len & 0x7F;
+                let field = bytes
+                    .get(pos..pos + len_bytes)
+                    .ok_or_else(|| invalid("truncated TRS header"))?;
+                len = field
+                    .iter()
+                    .rev()
+                    .fold(0, |acc, b| (acc << 8) | *b as usize);
+                pos += len_bytes;
+            }
+            let value = bytes
+                .get(pos..pos + len)
+                .ok_or_else(|| invalid("truncated TRS header"))?;
+            pos += len;
+            let int = || {
+                value
+                    .iter()
+                    .rev()
+                    .fold(0, |acc, b| (acc << 8) | *b as usize)
+            };
+            let float = || f32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+            match tag {
+                TAG_TRACES_NO => header.traces_no = int(),
+                TAG_LEN_SAMPLES => header.len_samples = int(),
+                TAG_CODING => {
+                    let byte = value
+                        .first()
+                        .ok_or_else(|| invalid("empty TRS sample coding"))?;
+                    header.coding = SampleCoding::from_byte(*byte)?
+                }
+                TAG_DATA_LEN => header.data_len = int(),
+                TAG_TITLE_LEN => header.title_len = int(),
+                TAG_GLOBAL_TITLE => header.global_title = String::from_utf8_lossy(value).into(),
+                TAG_DESCRIPTION => header.description = String::from_utf8_lossy(value).into(),
+                TAG_X_SCALE if len == 4 => header.x_scale = float(),
+                TAG_Y_SCALE if len == 4 => header.y_scale = float(),
+                TAG_TRACE_BLOCK => break,
+                _ => {}
+            }
+        }
+        header.header_len = pos;
+        Ok(header)
+    }
+
+    /// Size in bytes of a trace in the file.
+    pub fn trace_len(&self) -> usize {
+        self.title_len + self.data_len + self.len_samples * self.coding.size()
+    }
+}
+
+pub struct TrsReader {
+    mmap: Mmap,
+    header: TrsHeader,
+    window: Range<usize>,
+}
+
+impl TrsReader {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path)?;
+        /* Safety: the file must not be modified while it is mapped */
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header = TrsHeader::parse(&mmap)?;
+        let len = header
+            .len_samples
+            .checked_mul(header.coding.size())
+            .and_then(|len| len.checked_add(header.title_len))
+            .and_then(|len| len.checked_add(header.data_len))
+            .and_then(|len| len.checked_mul(header.traces_no))
+            .and_then(|len| len.checked_add(header.header_len));
+        match len {
+            Some(len) if len <= mmap.len() => {}
+            _ => return Err(invalid(format!("{path} is truncated"))),
+        }
+        Ok(Self {
+            window: 0..header.len_samples,
+            mmap,
+            header,
+        })
+    }
+
+    pub fn header(&self) -> &TrsHeader {
+        &self.header
+    }
+
+    /// Restricts the returned traces to the samples start..end.
+    pub fn window(&mut self, start: usize, end: usize) {
+        assert!(start <= end && end <= self.header.len_samples);
+        self.window = start..end;
+    }
+
+    pub fn len_traces(&self) -> usize {
+        self.header.traces_no
+    }
+
+    /// Number of samples in the window.
+    pub fn len_samples(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Traces start..start + len (clipped to the file) and their data bytes.
+    pub fn batch(&self, start: usize, len: usize) -> (Array2<f32>, Array2<u8>) {
+        let end = (start + len).min(self.len_traces());
+        let start = start.min(end);
+        let header = &self.header;
+        let size = header.coding.size();
+        let mut traces = Array2::zeros((end - start, self.len_samples()));
+        let mut data = Array2::zeros((end - start, header.data_len));
+        for (row, i) in (start..end).enumerate() {
+            let offset = header.header_len + i * header.trace_len() + header.title_len;
+            let bytes = &self.mmap[offset..offset + header.trace_len() - header.title_len];
+            data.row_mut(row)
+                .iter_mut()
+                .zip(&bytes[..header.data_len])
+                .for_each(|(d, b)| *d = *b);
+            let samples = bytes[header.data_len..]
+                .chunks_exact(size)
+                .skip(self.window.start);
+            traces
+                .row_mut(row)
+                .iter_mut()
+                .zip(samples)
+                .for_each(|(t, s)| *t = header.coding.decode(s) * header.y_scale);
+        }
+        (traces, data)
+    }
+
+    /// Data bytes of the traces start..start + len (clipped to the file).
+    pub fn data(&self, start: usize, len: usize) -> Array2<u8> {
+        let end = (start + len).min(self.len_traces());
+        let start = start.min(end);
+        let header = &self.header;
+        Array2::from_shape_fn((end - start, header.data_len), |(row, j)| {
+            let offset = header.header_len + (start + row) * header.trace_len();
+            self.mmap[offset + header.title_len + j]
+        })
+    }
+
+    /// Batches of `len` traces in order.
+    pub fn batches(&self, len: usize) -> impl Iterator<Item = (Array2<f32>, Array2<u8>)> + '_ {
+        (0..self.len_traces())
+            .step_by(len)
+            .map(move |start| self.batch(start, len))
+    }
+}
+
+impl TraceSource for TrsReader {
+    fn len_traces(&self) -> usize {
+        TrsReader::len_traces(self)
+    }
+
+    fn len_samples(&self) -> usize {
+        TrsReader::len_samples(self)
+    }
+
+    fn width(&self) -> usize {
+        self.header.len_samples
+    }
+
+    fn window(&mut self, start: usize, end: usize) {
+        TrsReader::window(self, start, end);
+    }
+
+    fn batch(&self, start: usize, len: usize) -> Array2<f32> {
+        TrsReader::batch(self, start, len).0
+    }
+}
+
+impl MetadataSource for TrsReader {
+    fn len_traces(&self) -> usize {
+        TrsReader::len_traces(self)
+    }
+
+    fn metadata(&self, start: usize, len: usize) -> Result<Array2<u8>> {
+        Ok(TrsReader::data(self, start, len))
+    }
+}
+
+/// Writes traces and their data bytes to a TRS file. The number of traces and
+/// the scales are written in the header by `finish`.
+pub struct TrsWriter {
+    writer: BufWriter<File>,
+    coding: SampleCoding,
+    len_samples: usize,
+    data_len: usize,
+    traces_no: usize,
+    x_scale: f32,
+    y_scale: f32,
+    round: bool,
+}
+
+/* Offsets of the values patched by finish in the header written by create */
+const TRACES_NO_OFFSET: u64 = 2;
+const X_SCALE_OFFSET: u64 = 17;
+const Y_SCALE_OFFSET: u64 = 23;
+
+impl TrsWriter {
+    pub fn create(
+        path: &str,
+        len_samples: usize,
+        data_len: usize,
+        coding: SampleCoding,
+    ) -> Result<Self> {
+        let too_large = |what: &str| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{what} does not fit in a TRS header"),
+            )
+        };
+        let len_samples_field =
+            u32::try_from(len_samples).map_err(|_| too_large("the number of samples"))?;
+        let data_len_field =
+            u16::try_from(data_len).map_err(|_| too_large("the number of data bytes"))?;
+        let mut header = Vec::new();
+        let mut object = |tag: u8, value: &[u8]| {
+            header.push(tag);
+            header.push(value.len() as u8);
+            header.extend_from_slice(value);
+        };
+        object(TAG_TRACES_NO, &0u32.to_le_bytes());
+        object(TAG_LEN_SAMPLES, &len_samples_field.to_le_bytes());
+        object(TAG_CODING, &[coding.to_byte()]);
+        object(TAG_X_SCALE, &1f32.to_le_bytes());
+        object(TAG_Y_SCALE, &1f32.to_le_bytes());
+        object(TAG_DATA_LEN, &data_len_field.to_le_bytes());
+        object(TAG_TRACE_BLOCK, &[]);
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&header)?;
+        Ok(Self {
+            writer,
+            coding,
+            len_samples,
+            data_len,
+            traces_no: 0,
+            x_scale: 1.0,
+            y_scale: 1.0,
+            round: false,
+        })
+    }
+
+    /// Time between samples (x) and value of one unit of the coding (y). The
+    /// samples are divided by the y scale when written, so it must be set
+    /// before the first batch.
+    pub fn scale(&mut self, x_scale: f32, y_scale: f32) {
+        assert!(
+            self.traces_no == 0,
+            "the scale must be set before writing traces"
+        );
+        assert!(y_scale.is_normal(), "invalid y scale {y_scale}");
+        self.x_scale = x_scale;
+        self.y_scale = y_scale;
+    }
+
+    /// Rounds the samples divided by the y scale to the nearest integer for
+    /// the integer codings, instead of rejecting the samples that are not a
+    /// multiple of the y scale. Samples out of the range of the coding are
+    /// rejected either way.
+    pub fn round(&mut self, round: bool) {
+        self.round = round;
+    }
+
+    /// Appends a batch of traces (one per row) with their data bytes.
+    /// Samples that the coding cannot hold are an error.
+    pub fn write_batch(&mut self, traces: ArrayView2<f32>, data: ArrayView2<u8>) -> Result<()> {
+        if traces.shape()[1] != self.len_samples
+            || data.shape()[1] != self.data_len
+            || traces.shape()[0] != data.shape()[0]
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "batch shape does not match the trace set",
+            ));
+        }
+        let mut bytes = Vec::with_capacity(self.data_len + self.len_samples * self.coding.size());
+        for (trace, data) in traces.outer_iter().zip(data.outer_iter()) {
+            bytes.clear();
+            bytes.extend(data.iter());
+            for sample in trace.iter() {
+                self.coding
+                    .encode(*sample / self.y_scale, self.round, &mut bytes)?;
+            }
+            self.writer.write_all(&bytes)?;
+        }
+        self.traces_no += traces.shape()[0];
+        Ok(())
+    }
+
+    /// Writes the number of traces and the scales in the header and flushes
+    /// the file.
+    pub fn finish(mut self) -> Result<()> {
+        let traces_no = u32::try_from(self.traces_no).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "the number of traces does not fit in a TRS header",
+            )
+        })?;
+        for (offset, value) in [
+            (TRACES_NO_OFFSET, traces_no.to_le_bytes()),
+            (X_SCALE_OFFSET, self.x_scale.to_le_bytes()),
+            (Y_SCALE_OFFSET, self.y_scale.to_le_bytes()),
+        ] {
+            self.writer.seek(SeekFrom::Start(offset))?;
+            self.writer.write_all(&value)?;
+        }
+        self.writer.flush()
+    }
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn round_trip() {
+        let traces = array![[-1.5f32, 0.0, 2.25, 100.0], [3.0, -4.0, 0.5, -100.0]];
+        let data = array![[1u8, 2, 3], [4, 5, 6]];
+        for coding in [
+            SampleCoding::Int8,
+            SampleCoding::Int16,
+            SampleCoding::Int32,
+            SampleCoding::Float32,
+        ] {
+            let path = std::env::temp_dir().join(format!("cpa_round_trip_{coding:?}.trs"));
+            let path = path.to_str().unwrap();
+            let mut writer = TrsWriter::create(path, 4, 3, coding).unwrap();
+            writer.scale(1e-9, 0.25);
+            if coding == SampleCoding::Int8 {
+                /* 100 / 0.25 does not fit in an i8 */
+                assert!(writer.write_batch(traces.view(), data.view()).is_err());
+                writer = TrsWriter::create(path, 4, 3, coding).unwrap();
+                writer.scale(1e-9, 1.0);
+                /* -1.5 is not a multiple of the y scale */
+                assert!(writer.write_batch(traces.view(), data.view()).is_err());
+                writer = TrsWriter::create(path, 4, 3, coding).unwrap();
+                writer.scale(1e-9, 1.0);
+                writer.round(true);
+            }
+            writer.write_batch(traces.view(), data.view()).unwrap();
+            writer.write_batch(traces.view(), data.view()).unwrap();
+            writer.finish().unwrap();
+
+            let reader = TrsReader::open(path).unwrap();
+            let header = reader.header();
+            assert_eq!(header.traces_no, 4);
+            assert_eq!(header.len_samples, 4);
+            assert_eq!(header.coding, coding);
+            assert_eq!(header.data_len, 3);
+            assert_eq!(header.x_scale, 1e-9);
+            let (read, read_data) = reader.batch(2, 10);
+            assert_eq!(read_data, data);
+            if coding == SampleCoding::Int8 {
+                /* Rounded to the nearest integer, half away from zero */
+                assert_eq!(header.y_scale, 1.0);
+                assert_eq!(read, traces.mapv(f32::round));
+            } else {
+                assert_eq!(header.y_scale, 0.25);
+                assert_eq!(read, traces);
+            }
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn header_fields_too_large() {
+        let path = std::env::temp_dir().join("cpa_too_large.trs");
+        let path = path.to_str().unwrap();
+        let error = TrsWriter::create(path, 1, 1 << 16, SampleCoding::Float32)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn invalid_headers() {
+        /* A coding tag without value */
+        let bytes = [TAG_CODING, 0, TAG_TRACE_BLOCK, 0];
+        assert!(TrsHeader::parse(&bytes).is_err());
+        /* A trace count whose size overflows */
+        let path = std::env::temp_dir().join("cpa_overflow.trs");
+        let path = path.to_str().unwrap();
+        let mut bytes = vec![TAG_TRACES_NO, 8];
+        bytes.extend((usize::MAX / 2).to_le_bytes());
+        bytes.extend([TAG_LEN_SAMPLES, 1, 4, TAG_TRACE_BLOCK, 0]);
+        std::fs::write(path, bytes).unwrap();
+        let error = TrsReader::open(path).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        std::fs::remove_file(path).unwrap();
+    }
+}